@@ -0,0 +1,521 @@
+use bevy::prelude::*;
+use rand::Rng;
+use rand::rngs::StdRng;
+use std::collections::HashMap;
+
+use crate::ai::tuning::AiTuning;
+use crate::components::{AiKind, Awareness, Footprint, PathCache, Patrol};
+use crate::engine::rules::{MoveCheck, Rules, check_step};
+use crate::grid::{Dir, GridCoord, MovementMode, OccupancyIndex, line_of_sight};
+use crate::intents::{
+    Intent, UNBOUNDED_CHASE_COST, follow_cached_path, grid_step_to_dir, random_legal_step,
+};
+use crate::pathfinding::astar::{SearchAlgorithm, astar_footprint_partial, astar_footprint_traced};
+use crate::pathfinding::dijkstra::DijkstraMap;
+use crate::pathfinding::flow::FlowField;
+use crate::pathfinding::hpa::HpaGraph;
+use crate::pathfinding::keys::{keyed_path, keyed_path_traced};
+use crate::pathfinding::policy::RulesPolicy;
+use crate::pathfinding::stats::{AiPlanTrace, PathDiagnostics, PlanSource, SearchStats};
+
+/// How strongly a `Coward` prefers open space over simply moving away (see `DijkstraMap::inverted`).
+const FLEE_COEFFICIENT: f32 = 1.2;
+/// How often a `Chaser` takes a random step instead of its planned one, unless its kind's
+/// `AiTuning` says otherwise.
+pub const CHASER_WANDER_CHANCE: f64 = 0.10;
+/// How far a patrolling guard can see, in cells.
+pub const PATROL_SIGHT_RANGE: i32 = 6;
+
+/// How one kind of AI picks its move each turn. Registered in `AiBehaviours` under the
+/// `kind` names levels use, and run by `plan_ai` once per AI.
+pub trait AiBehaviour: Send + Sync + 'static {
+    fn plan(&self, ctx: &mut AiContext) -> Intent;
+}
+
+/// Everything a behaviour may look at while planning one AI's turn.
+pub struct AiContext<'a> {
+    pub entity: Entity,
+    pub position: GridCoord,
+    pub footprint: Footprint,
+    pub facing: Option<Dir>,
+    /// Where the player stands, or for an AI with `Perception`, where it believes they are:
+    /// the last-known cell once it lost them, and its own cell before it ever noticed them.
+    pub target: GridCoord,
+    /// What an AI with `Perception` knows of the player this turn; `None` for AIs that always know.
+    pub awareness: Option<Awareness>,
+    /// This AI's kind tuning, scaled by the current `Difficulty`.
+    pub tuning: AiTuning,
+    // Where the player really stands; AIs hunting that cell can share fields and cooperative plans
+    pub(crate) player: GridCoord,
+    pub occ: &'a OccupancyIndex,
+    pub rules: &'a dyn Rules,
+    pub movement: MovementMode,
+    /// The search this AI's own paths run when it covers a single tile.
+    pub search: SearchAlgorithm,
+    /// The turn RNG; behaviours must draw all randomness from it to keep replays deterministic.
+    pub rng: &'a mut StdRng,
+    pub cache: Option<&'a mut PathCache>,
+    pub diagnostics: &'a mut PathDiagnostics,
+    pub patrol: Option<&'a mut Patrol>,
+    pub(crate) hpa: &'a HpaGraph,
+    pub(crate) fields: &'a mut SharedFields,
+    pub(crate) cooperative: Option<&'a mut Vec<(Entity, GridCoord, bool)>>,
+}
+
+/// Fields built at most once per turn and shared by every AI that reads them.
+#[derive(Default)]
+pub(crate) struct SharedFields {
+    // keyed by target, since AIs with `Targeting` or `Perception` hunt and flee cells of their own
+    chase: HashMap<GridCoord, FlowField>,
+    flee: HashMap<GridCoord, DijkstraMap>,
+}
+
+impl<'a> AiContext<'a> {
+    /// This AI's path policy under the active rules, with the level's search and its tuned
+    /// per-search node budget.
+    pub fn policy(&self) -> RulesPolicy<'a> {
+        RulesPolicy::new(self.rules, self.occ, self.movement, self.entity)
+            .with_algorithm(self.search)
+            .with_max_expansions(Some(self.tuning.search_budget))
+    }
+
+    // One policy for fields every AI shares: nobody's own cell counts as free
+    fn shared_policy(&self) -> RulesPolicy<'a> {
+        RulesPolicy::new(self.rules, self.occ, self.movement, Entity::PLACEHOLDER)
+    }
+
+    fn max_field_cost(&self) -> i32 {
+        if self.occ.bounds().is_some() {
+            i32::MAX
+        } else {
+            UNBOUNDED_CHASE_COST
+        }
+    }
+
+    /// True when `dir` is a legal step for this AI under the active rules.
+    pub fn can_step(&self, dir: Dir) -> bool {
+        let check = check_step(
+            self.rules,
+            self.occ,
+            self.movement,
+            self.entity,
+            self.footprint,
+            self.position,
+            dir,
+        );
+        matches!(check, MoveCheck::Allow)
+    }
+
+    /// A random legal step, or `None` when boxed in.
+    pub fn random_step(&mut self) -> Option<Dir> {
+        random_legal_step(
+            self.rules,
+            self.occ,
+            self.movement,
+            self.entity,
+            self.footprint,
+            self.position,
+            self.rng,
+        )
+    }
+
+    /// Intent stepping onto `path[1]`; `Wait` when the path has nowhere to go.
+    pub fn follow(&self, path: &[GridCoord]) -> Intent {
+        path.get(1)
+            .and_then(|&next| grid_step_to_dir(self.occ, self.position, next))
+            .map_or(Intent::Wait, Intent::Move)
+    }
+
+    /// Step along this turn's shared flow field towards `target`, built on first use.
+    /// `None` on the target or where the field does not reach.
+    pub fn chase_step(&mut self) -> Option<Dir> {
+        let shared = self.shared_policy();
+        let max_cost = self.max_field_cost();
+        let diagnostics = &mut *self.diagnostics;
+        let field = self.fields.chase.entry(self.target).or_insert_with(|| {
+            if diagnostics.enabled && diagnostics.chase_field.is_none() {
+                let mut stats = SearchStats::default();
+                let field = FlowField::build_traced([self.target], &shared, max_cost, &mut stats);
+                diagnostics.chase_field = Some(stats);
+                field
+            } else {
+                FlowField::build([self.target], &shared, max_cost)
+            }
+        });
+        field.dir_at(self.position)
+    }
+
+    /// Next cell away from `target` on this turn's shared flee map, built on first use.
+    /// `None` when no neighbour is further away.
+    pub fn flee_step(&mut self) -> Option<GridCoord> {
+        let policy = self.policy();
+        let shared = self.shared_policy();
+        let max_cost = self.max_field_cost();
+        let flee = self.fields.flee.entry(self.target).or_insert_with(|| {
+            DijkstraMap::build([self.target], &shared, max_cost).inverted(
+                FLEE_COEFFICIENT,
+                &shared,
+                max_cost,
+            )
+        });
+        flee.next_step(self.position, &policy)
+    }
+
+    /// True unless the AI has `Perception` and has not detected the player this turn.
+    pub fn detects_player(&self) -> bool {
+        self.awareness
+            .is_none_or(|a| matches!(a, Awareness::Detected(_)))
+    }
+
+    /// True when the player is detected and `can_see` them within `range`.
+    pub fn sees_player(&self, range: i32) -> bool {
+        self.detects_player() && self.can_see(self.target, range)
+    }
+
+    /// True when `c` is within `range` cells (straight-line distance) and no wall or door
+    /// stands in between.
+    pub fn can_see(&self, c: GridCoord, range: i32) -> bool {
+        let (dx, dy) = self.occ.delta(self.position, c);
+        dx * dx + dy * dy <= range * range && line_of_sight(self.occ, self.position, c)
+    }
+
+    /// Next step towards `goal`: the cached path while it still leads there, otherwise a
+    /// fresh A* search (or the closest it gets within the budget), cached for later turns.
+    pub fn walk_to(&mut self, goal: GridCoord, source: PlanSource) -> Intent {
+        let policy = self.policy();
+        if let Some(cache) = self.cache.as_deref_mut()
+            && let Some(next) =
+                follow_cached_path(cache, self.position, goal, self.footprint, &policy)
+        {
+            self.diagnostics.record_cached(self.entity, &cache.path);
+            return grid_step_to_dir(self.occ, self.position, next)
+                .map_or(Intent::Wait, Intent::Move);
+        }
+        let path = self.search_to(goal, source);
+        let intent = self.follow(&path);
+        if let Some(cache) = self.cache.as_deref_mut()
+            && path.len() >= 2
+        {
+            cache.store(goal, path);
+        }
+        intent
+    }
+
+    // A* (or the level's jump point search) over this AI's footprint to `goal`, or as close as
+    // it gets, recorded as its plan
+    fn search_to(&mut self, goal: GridCoord, source: PlanSource) -> Vec<GridCoord> {
+        let policy = self.policy();
+        let mut stats = self.diagnostics.new_stats();
+        let path = match stats.as_mut() {
+            Some(stats) => {
+                astar_footprint_traced(self.position, goal, self.footprint, &policy, stats)
+            }
+            None => astar_footprint_partial(self.position, goal, self.footprint, &policy),
+        }
+        .into_path();
+        self.diagnostics
+            .record_search(self.entity, source, &path, stats);
+        path
+    }
+
+    /// Leave this AI to cooperative planning, which runs once every other AI has planned.
+    /// Only single-tile AIs hunting the player's own cell take part, and only on levels that
+    /// turn it on; returns whether it did.
+    pub fn join_cooperative(&mut self, wander: bool) -> bool {
+        if self.target != self.player {
+            return false;
+        }
+        let Some(movers) = self.cooperative.as_deref_mut() else {
+            return false;
+        };
+        if !self.footprint.is_single() {
+            return false;
+        }
+        if let Some(cache) = self.cache.as_deref_mut() {
+            cache.clear();
+        }
+        movers.push((self.entity, self.position, wander));
+        true
+    }
+}
+
+// The state an AI's actions may change while planning, restored when a tree node fails or a
+// utility option falls through
+pub(crate) struct Undo {
+    patrol: Option<Patrol>,
+    cache: Option<PathCache>,
+    plan: Option<AiPlanTrace>,
+    // how many AIs had joined cooperative planning
+    cooperative: Option<usize>,
+}
+
+impl Undo {
+    pub(crate) fn take(ctx: &AiContext) -> Self {
+        Self {
+            patrol: ctx.patrol.as_deref().cloned(),
+            cache: ctx.cache.as_deref().cloned(),
+            plan: ctx.diagnostics.plans.get(&ctx.entity).cloned(),
+            cooperative: ctx.cooperative.as_deref().map(Vec::len),
+        }
+    }
+
+    pub(crate) fn restore(self, ctx: &mut AiContext) {
+        if let (Some(patrol), Some(before)) = (ctx.patrol.as_deref_mut(), self.patrol) {
+            *patrol = before;
+        }
+        if let (Some(cache), Some(before)) = (ctx.cache.as_deref_mut(), self.cache) {
+            *cache = before;
+        }
+        match self.plan {
+            Some(plan) => ctx.diagnostics.plans.insert(ctx.entity, plan),
+            None => ctx.diagnostics.plans.remove(&ctx.entity),
+        };
+        if let (Some(movers), Some(before)) = (ctx.cooperative.as_deref_mut(), self.cooperative) {
+            movers.truncate(before);
+        }
+    }
+}
+
+/// Behaviours by `kind`. Starts with the built-in ones; games add their own with `register`.
+/// Kinds that were never registered, and AIs without an `AiKind`, chase.
+#[derive(Resource)]
+pub struct AiBehaviours {
+    kinds: HashMap<String, Box<dyn AiBehaviour>>,
+    fallback: Box<dyn AiBehaviour>,
+}
+
+impl Default for AiBehaviours {
+    fn default() -> Self {
+        let mut behaviours = Self {
+            kinds: HashMap::new(),
+            fallback: Box::new(Chaser::default()),
+        };
+        behaviours
+            .register("chaser", Chaser::default())
+            .register("ghost", Chaser::default())
+            .register("wanderer", Wanderer)
+            .register("patroller", Patroller)
+            .register("coward", Coward);
+        behaviours
+    }
+}
+
+impl AiBehaviours {
+    /// Run `behaviour` for AIs of `kind`, replacing any behaviour registered under it before.
+    pub fn register(&mut self, kind: impl Into<String>, behaviour: impl AiBehaviour) -> &mut Self {
+        self.kinds.insert(kind.into(), Box::new(behaviour));
+        self
+    }
+
+    pub fn get(&self, kind: &str) -> Option<&dyn AiBehaviour> {
+        self.kinds.get(kind).map(|b| b.as_ref())
+    }
+
+    /// Behaviour for an AI with this kind, falling back to `Chaser`.
+    pub fn for_kind(&self, kind: Option<&AiKind>) -> &dyn AiBehaviour {
+        kind.and_then(|k| self.get(&k.0))
+            .unwrap_or(self.fallback.as_ref())
+    }
+}
+
+/// Hunts the player: with probability `AiTuning::randomness` a random step, otherwise the best
+/// route it can find. Door openers search over keys; large levels use the HPA* graph,
+/// multi-tile AIs A*, and everyone else the shared flow field. Routes are cached between turns.
+#[derive(Default)]
+pub struct Chaser {
+    /// Random-step chance used instead of the kind's tuning, e.g. `Some(0.0)` for a steady chaser.
+    pub wander_chance: Option<f64>,
+}
+
+impl AiBehaviour for Chaser {
+    fn plan(&self, ctx: &mut AiContext) -> Intent {
+        // Without the player in view or earshot: check where they were last seen, then look around
+        match ctx.awareness {
+            Some(Awareness::Investigating(last_known)) => {
+                return ctx.walk_to(last_known, PlanSource::Search);
+            }
+            Some(Awareness::Searching { .. } | Awareness::Unaware) => {
+                return Wanderer.plan(ctx);
+            }
+            Some(Awareness::Detected(_)) | None => {}
+        }
+
+        let (entity, start, target, footprint) =
+            (ctx.entity, ctx.position, ctx.target, ctx.footprint);
+
+        // With small probability, take a random legal step (stochastic behavior)
+        let wander = ctx
+            .rng
+            .gen_bool(self.wander_chance.unwrap_or(ctx.tuning.randomness));
+
+        let key_ring = ctx.occ.key_ring(entity).filter(|_| footprint.is_single());
+
+        // planned together with the other cooperative movers, once every other AI's step is known
+        if key_ring.is_none() && ctx.join_cooperative(wander) {
+            return Intent::Wait;
+        }
+
+        // falls through to A* if no legal random step
+        if wander && let Some(dir) = ctx.random_step() {
+            let path = [start, ctx.occ.step(start, dir)];
+            ctx.diagnostics.record(entity, PlanSource::Wander, &path);
+            return Intent::Move(dir);
+        }
+
+        let policy = &ctx.policy();
+
+        // Door openers search over the keys they could pick up on the way
+        if let Some(ring) = key_ring {
+            let layout = ctx.occ.key_layout();
+            let mut stats = ctx.diagnostics.new_stats();
+            let route = match stats.as_mut() {
+                Some(stats) => keyed_path_traced(start, target, ring, layout, policy, stats),
+                None => keyed_path(start, target, ring, layout, policy),
+            };
+            if let Some(route) = route {
+                ctx.diagnostics
+                    .record_search(entity, PlanSource::Keyed, &route.path, stats);
+                return ctx.follow(&route.path);
+            }
+        }
+
+        // Keep following last turn's path while it is still good
+        if let Some(cache) = ctx.cache.as_deref_mut()
+            && let Some(next) = follow_cached_path(cache, start, target, footprint, policy)
+        {
+            ctx.diagnostics.record_cached(entity, &cache.path);
+            return grid_step_to_dir(ctx.occ, start, next).map_or(Intent::Wait, Intent::Move);
+        }
+
+        // Path is [start, step1, step2, ..., goal]
+        let path = if !footprint.is_single() || target != ctx.player {
+            // Multi-tile movers, and chasers aiming off the player (maybe into a wall): the A*
+            // optimal path for their footprint, or as close as they can get
+            ctx.search_to(target, PlanSource::AStar)
+        } else {
+            // Large levels: search the cluster graph instead of flooding the whole map
+            let mut stats = ctx.diagnostics.new_stats();
+            let hpa = match stats.as_mut() {
+                Some(stats) => ctx.hpa.find_path_traced(start, target, policy, stats),
+                None => ctx.hpa.find_path(start, target, policy),
+            };
+            let Some(path) = hpa else {
+                // Single-tile chasers all step along one flow field to the player, built once
+                // per turn; nothing to cache, since next turn's field has the step ready too
+                let dir = ctx.chase_step();
+                let path: Vec<GridCoord> = std::iter::once(start)
+                    .chain(dir.map(|d| ctx.occ.step(start, d)))
+                    .collect();
+                ctx.diagnostics.record(entity, PlanSource::FlowField, &path);
+                return dir.map_or(Intent::Wait, Intent::Move);
+            };
+            ctx.diagnostics
+                .record_search(entity, PlanSource::Hpa, &path, stats);
+            path
+        };
+
+        if path.len() < 2 {
+            // No path found, or nothing to do → wait
+            return Intent::Wait;
+        }
+
+        let intent = ctx.follow(&path);
+        if let Some(cache) = ctx.cache.as_deref_mut() {
+            cache.store(target, path);
+        }
+        intent
+    }
+}
+
+/// Ignores the player and takes a random legal step every turn.
+pub struct Wanderer;
+
+impl AiBehaviour for Wanderer {
+    fn plan(&self, ctx: &mut AiContext) -> Intent {
+        match ctx.random_step() {
+            Some(dir) => {
+                let path = [ctx.position, ctx.occ.step(ctx.position, dir)];
+                ctx.diagnostics
+                    .record(ctx.entity, PlanSource::Wander, &path);
+                Intent::Move(dir)
+            }
+            None => Intent::Wait,
+        }
+    }
+}
+
+/// Guards a route. With a `Patrol` it walks between the waypoints, gives chase while it can
+/// see the player within `PATROL_SIGHT_RANGE`, and heads back to its next waypoint once the
+/// player is out of sight. Without one it paces back and forth, turning around when blocked
+/// and trying the other directions in order when both ways are shut.
+pub struct Patroller;
+
+impl AiBehaviour for Patroller {
+    fn plan(&self, ctx: &mut AiContext) -> Intent {
+        if ctx.patrol.is_some() {
+            // a guard with `Perception` also checks where it lost the player before going back
+            let alerted = match ctx.awareness {
+                Some(awareness) => matches!(
+                    awareness,
+                    Awareness::Detected(_) | Awareness::Investigating(_)
+                ),
+                None => ctx.can_see(ctx.target, PATROL_SIGHT_RANGE),
+            };
+            if alerted {
+                return Chaser::default().plan(ctx);
+            }
+            return follow_patrol(ctx);
+        }
+
+        let facing = ctx.facing.unwrap_or(Dir::Right);
+        let turn = [facing, facing.opposite()]
+            .into_iter()
+            .chain(ctx.movement.dirs().iter().copied())
+            .find(|&dir| ctx.movement.allows(dir) && ctx.can_step(dir));
+        match turn {
+            Some(dir) => {
+                let path = [ctx.position, ctx.occ.step(ctx.position, dir)];
+                ctx.diagnostics
+                    .record(ctx.entity, PlanSource::Patrol, &path);
+                Intent::Move(dir)
+            }
+            None => Intent::Wait,
+        }
+    }
+}
+
+/// Next step along the AI's patrol route; `Wait` without a route or with nowhere to go.
+pub(crate) fn follow_patrol(ctx: &mut AiContext) -> Intent {
+    let (position, footprint) = (ctx.position, ctx.footprint);
+    let Some(patrol) = ctx.patrol.as_deref_mut() else {
+        return Intent::Wait;
+    };
+    let reached = |c: GridCoord| footprint.cells(position).any(|cell| cell == c);
+    if patrol.target().is_some_and(reached) {
+        patrol.advance(ctx.rng);
+    }
+    match patrol.target() {
+        Some(waypoint) if !reached(waypoint) => ctx.walk_to(waypoint, PlanSource::Patrol),
+        _ => Intent::Wait,
+    }
+}
+
+/// Runs from the player along this turn's shared flee map, heading for open space rather
+/// than the nearest corner; waits once nowhere is further away, or until it detects the player.
+pub struct Coward;
+
+impl AiBehaviour for Coward {
+    fn plan(&self, ctx: &mut AiContext) -> Intent {
+        if !ctx.detects_player() {
+            return Intent::Wait;
+        }
+        match ctx.flee_step() {
+            Some(next) => {
+                let path = [ctx.position, next];
+                ctx.diagnostics.record(ctx.entity, PlanSource::Flee, &path);
+                ctx.follow(&path)
+            }
+            None => Intent::Wait,
+        }
+    }
+}
@@ -0,0 +1,598 @@
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use std::collections::HashMap;
+
+use crate::ai::tuning::{AiTunings, Difficulty};
+use crate::components::{
+    AI, Actor, AiKind, Awareness, Blocking, Door, Energy, Footprint, Goal, Key, KeyRing, Lock,
+    Lookahead, PendingIntent, Player, Position, Speed, Trap,
+};
+use crate::engine::rules::{
+    ActiveRules, DefaultRules, GetCaught, MoveCheck, MoveConflict, ReachedGoal, Rules,
+    SteppedOnTrap, check_step,
+};
+use crate::engine::schedule::{
+    ExtraPhase, acts_in_phase, cleanup_turn, commit_changes, fire_on_enter_hooks, grant_energy,
+    pick_up_keys, resolve_conflicts, run_extra_phases, validate_moves,
+};
+use crate::engine::{TurnNumber, TurnPhase, TurnRng};
+use crate::grid::{Dir, GridCoord, MovementMode, OccupancyIndex, rebuild_occupancy};
+use crate::intents::Intent;
+use crate::pathfinding::stats::{PathDiagnostics, PlanSource};
+
+/// Runs in `TurnSystems::AiPlan` after `plan_ai`. Every AI with `Lookahead` that knows where the
+/// player is, within its kind's aggression radius, tries each legal step on a copy of the world:
+/// it plays `rollouts` games of `depth` turns and keeps the step that caught the player in the
+/// most games, finishing closest to them on a tie. The games draw from one seed taken from
+/// `TurnRng`, so replays stay deterministic.
+///
+/// The games run the real energy, resolve, commit and extra-phase systems, but not AI planning:
+/// after its opening the hunter takes the legal step that ends nearest the player, the other
+/// AIs make the moves `plan_ai` gave them this turn and then stand still, and the player
+/// answers with random legal steps.
+pub fn plan_lookahead(world: &mut World) {
+    let mut q_player = world.query_filtered::<&Position, With<Player>>();
+    let Some(player) = q_player.iter(world).next().map(|p| p.0) else {
+        return;
+    };
+    let mut q = world.query_filtered::<(
+        Entity,
+        &Position,
+        &Lookahead,
+        Option<&Awareness>,
+        Option<&AiKind>,
+        Option<&Energy>,
+    ), With<AI>>();
+    let difficulty = world
+        .get_resource::<Difficulty>()
+        .copied()
+        .unwrap_or_default();
+    let default_tunings = AiTunings::default();
+    let tunings = world
+        .get_resource::<AiTunings>()
+        .unwrap_or(&default_tunings);
+    let phase = world
+        .get_resource::<TurnPhase>()
+        .copied()
+        .unwrap_or_default();
+    let occ = world.resource::<OccupancyIndex>();
+    // the same AIs `plan_ai` lets go after the player, if they act in this phase
+    let mut hunters: Vec<(Entity, Lookahead)> = q
+        .iter(world)
+        .filter(|(_, pos, _, awareness, kind, energy)| {
+            let within = tunings
+                .for_kind(*kind, difficulty)
+                .aggression_radius
+                .is_none_or(|r| {
+                    let (dx, dy) = occ.delta(pos.0, player);
+                    dx * dx + dy * dy <= r * r
+                });
+            within
+                && acts_in_phase(*energy, phase)
+                && awareness.is_none_or(|a| matches!(a, Awareness::Detected(_)))
+        })
+        .map(|(e, _, lookahead, ..)| (e, *lookahead))
+        .collect();
+    if hunters.is_empty() {
+        return;
+    }
+    hunters.sort_by_key(|(e, _)| *e);
+
+    let seed = world.resource_mut::<TurnRng>().0.r#gen::<u64>();
+    // the copy borrows the live rules for the length of the scope, without marking them changed
+    let picked = world
+        .try_resource_scope(|world, mut rules: Mut<ActiveRules>| {
+            let live = rules.bypass_change_detection();
+            let lent = std::mem::replace(&mut live.0, Box::new(DefaultRules));
+            let (picked, lent) = pick_openings(world, &hunters, seed, lent);
+            live.0 = lent;
+            picked
+        })
+        .unwrap_or_else(|| pick_openings(world, &hunters, seed, Box::new(DefaultRules)).0);
+
+    for (hunter, intent) in picked {
+        let Some(from) = world.get::<Position>(hunter).map(|p| p.0) else {
+            continue;
+        };
+        if let Some(mut pending) = world.get_mut::<PendingIntent>(hunter) {
+            pending.0 = intent;
+        }
+        let mut path = vec![from];
+        if let Intent::Move(dir) = intent {
+            path.push(world.resource::<OccupancyIndex>().step(from, dir));
+        }
+        if let Some(mut diagnostics) = world.get_resource_mut::<PathDiagnostics>() {
+            diagnostics.record(hunter, PlanSource::Lookahead, &path);
+        }
+    }
+}
+
+// Each hunter's best opening, in entity order, handing the rules back once done
+fn pick_openings(
+    world: &mut World,
+    hunters: &[(Entity, Lookahead)],
+    seed: u64,
+    rules: Box<dyn Rules>,
+) -> (Vec<(Entity, Intent)>, Box<dyn Rules>) {
+    let mut sim = TurnSimulation::new(world, rules);
+    let mut picked = Vec::new();
+    for &(hunter, lookahead) in hunters {
+        let intent = sim.best_opening(hunter, lookahead, seed);
+        // hunters planning after this one see where it is going
+        sim.set_intent(hunter, intent);
+        picked.push((hunter, intent));
+    }
+    (picked, sim.finish())
+}
+
+// One entity as the turn pipeline sees it, copied out of the live world
+#[derive(Debug, Clone)]
+struct EntityCopy {
+    live: Entity,
+    position: GridCoord,
+    intent: Option<Intent>,
+    footprint: Option<Footprint>,
+    key_ring: Option<KeyRing>,
+    lock: Option<Lock>,
+    key: Option<Key>,
+    speed: Option<Speed>,
+    energy: Option<Energy>,
+    markers: Markers,
+}
+
+impl EntityCopy {
+    // Actors move and keys get picked up; everything else stays put for the whole game
+    fn changes(&self) -> bool {
+        self.markers.actor || self.markers.player || self.markers.ai || self.key.is_some()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Markers {
+    player: bool,
+    ai: bool,
+    actor: bool,
+    blocking: bool,
+    door: bool,
+    goal: bool,
+    trap: bool,
+}
+
+type CopyQuery = (
+    Entity,
+    &'static Position,
+    Option<&'static PendingIntent>,
+    Option<&'static Footprint>,
+    Option<&'static KeyRing>,
+    Option<&'static Lock>,
+    Option<&'static Key>,
+    (Option<&'static Speed>, Option<&'static Energy>),
+    (
+        Has<Player>,
+        Has<AI>,
+        Has<Actor>,
+        Has<Blocking>,
+        Has<Door>,
+        Has<Goal>,
+        Has<Trap>,
+    ),
+);
+
+// The AI whose opening is being played out, and the player it hunts; the copy's `ExtraPhase`
+// reads it to keep the hunter closing in
+#[derive(Resource, Clone, Copy)]
+struct Hunt {
+    hunter: Entity,
+    player: Entity,
+}
+
+// A headless copy of the grid that replays the turn pipeline from the same starting point as
+// often as asked. It holds the live rules until `finish` hands them back. The player is copied
+// standing where the last turn left them, as what they do this turn is not known yet.
+//
+// The walls, doors, goals and traps are spawned once. Between games the copy is kept as it
+// started: every game ends by putting the actors back and respawning the keys they picked up.
+struct TurnSimulation {
+    world: World,
+    turn: Schedule,
+    dawn: Schedule,
+    refresh: Schedule,
+    entities: Vec<EntityCopy>,
+    // live entity -> its copy; keys get a new copy when a game picked them up
+    copies: HashMap<Entity, Entity>,
+    // the phase of the live turn the games start in
+    phase: TurnPhase,
+}
+
+impl TurnSimulation {
+    fn new(live: &mut World, rules: Box<dyn Rules>) -> Self {
+        let mut entities: Vec<EntityCopy> = live
+            .query::<CopyQuery>()
+            .iter(live)
+            .map(
+                |(e, pos, intent, footprint, ring, lock, key, (speed, energy), markers)| {
+                    EntityCopy {
+                        live: e,
+                        position: pos.0,
+                        intent: if markers.0 {
+                            Some(Intent::Wait)
+                        } else {
+                            intent.map(|i| i.0)
+                        },
+                        footprint: footprint.copied(),
+                        key_ring: ring.copied(),
+                        lock: lock.copied(),
+                        key: key.copied(),
+                        speed: speed.copied(),
+                        energy: energy.copied(),
+                        markers: Markers {
+                            player: markers.0,
+                            ai: markers.1,
+                            actor: markers.2,
+                            blocking: markers.3,
+                            door: markers.4,
+                            goal: markers.5,
+                            trap: markers.6,
+                        },
+                    }
+                },
+            )
+            .collect();
+        // spawn the copies in entity order, as the live world would iterate them
+        entities.sort_by_key(|c| c.live);
+
+        let mut world = World::new();
+        // the copy keeps the level bounds; the layers are rebuilt from the copied entities
+        world.insert_resource(
+            live.get_resource::<OccupancyIndex>()
+                .cloned()
+                .unwrap_or_default(),
+        );
+        world.insert_resource(
+            live.get_resource::<MovementMode>()
+                .copied()
+                .unwrap_or_default(),
+        );
+        world.insert_resource(
+            live.get_resource::<TurnNumber>()
+                .copied()
+                .unwrap_or_default(),
+        );
+        let phase = live
+            .get_resource::<TurnPhase>()
+            .copied()
+            .unwrap_or_default();
+        world.insert_resource(phase);
+        world.insert_resource(ActiveRules(rules));
+        world.init_resource::<Messages<ReachedGoal>>();
+        world.init_resource::<Messages<SteppedOnTrap>>();
+        world.init_resource::<Messages<GetCaught>>();
+        world.init_resource::<Messages<MoveConflict>>();
+
+        // the Resolve, Commit and Cleanup systems of `RulesPlugin`, in the same order; fast AIs
+        // act again in `ExtraPhase`, where the hunter keeps closing in
+        let mut turn = Schedule::default();
+        turn.add_systems(
+            (
+                rebuild_occupancy,
+                validate_moves,
+                resolve_conflicts,
+                commit_changes,
+                fire_on_enter_hooks,
+                pick_up_keys,
+                run_extra_phases,
+                cleanup_turn,
+            )
+                .chain(),
+        );
+        let mut extra = Schedule::new(ExtraPhase);
+        extra.add_systems(
+            (
+                rebuild_occupancy,
+                close_in,
+                validate_moves,
+                resolve_conflicts,
+                commit_changes,
+                fire_on_enter_hooks,
+                pick_up_keys,
+                cleanup_turn,
+            )
+                .chain(),
+        );
+        world.add_schedule(extra);
+        // what happens before the AIs plan a new turn
+        let mut dawn = Schedule::default();
+        dawn.add_systems(grant_energy);
+        let mut refresh = Schedule::default();
+        refresh.add_systems(rebuild_occupancy);
+
+        let copies = entities
+            .iter()
+            .map(|c| (c.live, spawn_copy(&mut world, c)))
+            .collect();
+        let mut sim = Self {
+            world,
+            turn,
+            dawn,
+            refresh,
+            entities,
+            copies,
+            phase,
+        };
+        sim.refresh.run(&mut sim.world);
+        sim
+    }
+
+    // Hand the rules back
+    fn finish(mut self) -> Box<dyn Rules> {
+        self.world
+            .remove_resource::<ActiveRules>()
+            .expect("the copy holds the rules until it finishes")
+            .0
+    }
+
+    fn set_intent(&mut self, live: Entity, intent: Intent) {
+        if let Some(copy) = self.entities.iter_mut().find(|c| c.live == live) {
+            copy.intent = Some(intent);
+        }
+        let copy = self.copies[&live];
+        self.set_copy_intent(copy, intent);
+    }
+
+    // Put every actor back as it was in the live world and respawn picked-up keys
+    fn reset(&mut self) {
+        for c in self.entities.iter().filter(|c| c.changes()) {
+            let copy = self.copies[&c.live];
+            let Ok(mut e) = self.world.get_entity_mut(copy) else {
+                // a key someone picked up
+                let respawned = spawn_copy(&mut self.world, c);
+                self.copies.insert(c.live, respawned);
+                continue;
+            };
+            e.insert(Position(c.position));
+            if let Some(intent) = c.intent {
+                e.insert(PendingIntent(intent));
+            }
+            if let Some(ring) = c.key_ring {
+                e.insert(ring);
+            }
+            if let Some(energy) = c.energy {
+                e.insert(energy);
+            }
+        }
+        self.world.insert_resource(self.phase);
+        self.world.resource_mut::<Messages<ReachedGoal>>().clear();
+        self.world.resource_mut::<Messages<SteppedOnTrap>>().clear();
+        self.world.resource_mut::<Messages<GetCaught>>().clear();
+        self.world.resource_mut::<Messages<MoveConflict>>().clear();
+        self.refresh.run(&mut self.world);
+    }
+
+    fn position(&self, copy: Entity) -> GridCoord {
+        position(&self.world, copy)
+    }
+
+    fn distance(&self, a: Entity, b: Entity) -> f32 {
+        let (dx, dy) = self
+            .world
+            .resource::<OccupancyIndex>()
+            .delta(self.position(a), self.position(b));
+        ((dx * dx + dy * dy) as f32).sqrt()
+    }
+
+    fn set_copy_intent(&mut self, copy: Entity, intent: Intent) {
+        if let Some(mut pending) = self.world.get_mut::<PendingIntent>(copy) {
+            pending.0 = intent;
+        }
+    }
+
+    // The hunter's opening that caught the player in the most games, the closest finish
+    // breaking ties; legal steps come first in `MovementMode::dirs` order, waiting last
+    fn best_opening(&mut self, hunter: Entity, lookahead: Lookahead, seed: u64) -> Intent {
+        let mut openings: Vec<Intent> = legal_steps(&self.world, self.copies[&hunter])
+            .into_iter()
+            .map(Intent::Move)
+            .collect();
+        openings.push(Intent::Wait);
+
+        let mut best = (Intent::Wait, 0, f32::INFINITY);
+        for opening in openings {
+            // every opening meets the same player answers
+            let mut rng = StdRng::seed_from_u64(seed);
+            let (mut caught, mut distance) = (0, 0.0);
+            for _ in 0..lookahead.rollouts {
+                let (was_caught, apart) = self.play(hunter, opening, lookahead.depth, &mut rng);
+                caught += u32::from(was_caught);
+                distance += apart;
+            }
+            if caught > best.1 || (caught == best.1 && distance < best.2) {
+                best = (opening, caught, distance);
+            }
+        }
+        best.0
+    }
+
+    // One game of `depth` turns with the hunter opening on `opening`, then the copy reset for
+    // the next. Returns whether the player was caught and how far apart the two ended up.
+    fn play(
+        &mut self,
+        hunter: Entity,
+        opening: Intent,
+        depth: u32,
+        rng: &mut StdRng,
+    ) -> (bool, f32) {
+        let outcome = self.play_out(hunter, opening, depth, rng);
+        self.reset();
+        outcome
+    }
+
+    // Every turn the player takes a random legal step or waits. The other AIs make the moves
+    // they hold in the live world on the first turn and stand still after; the hunter closes
+    // in on every turn after its opening, and in every extra phase its energy buys.
+    fn play_out(
+        &mut self,
+        hunter: Entity,
+        opening: Intent,
+        depth: u32,
+        rng: &mut StdRng,
+    ) -> (bool, f32) {
+        let hunter = self.copies[&hunter];
+        let Some(player) = self
+            .entities
+            .iter()
+            .find(|c| c.markers.player)
+            .map(|c| self.copies[&c.live])
+        else {
+            return (false, f32::INFINITY);
+        };
+        self.world.insert_resource(Hunt { hunter, player });
+
+        self.set_copy_intent(hunter, opening);
+        for turn in 0..depth {
+            let mut answers: Vec<Intent> = legal_steps(&self.world, player)
+                .into_iter()
+                .map(Intent::Move)
+                .collect();
+            answers.push(Intent::Wait);
+            let answer = *answers.choose(rng).unwrap_or(&Intent::Wait);
+            self.set_copy_intent(player, answer);
+            if turn > 0 {
+                self.dawn.run(&mut self.world);
+                let chase = closing_step(&self.world, hunter, player);
+                self.set_copy_intent(hunter, chase);
+            }
+            self.turn.run(&mut self.world);
+
+            let caught = self
+                .world
+                .resource_mut::<Messages<GetCaught>>()
+                .drain()
+                .count()
+                > 0;
+            if caught {
+                return (true, 0.0);
+            }
+            let escaped = self
+                .world
+                .resource_mut::<Messages<ReachedGoal>>()
+                .drain()
+                .count()
+                > 0;
+            self.refresh.run(&mut self.world);
+            if escaped {
+                break;
+            }
+        }
+        (false, self.distance(hunter, player))
+    }
+}
+
+fn spawn_copy(world: &mut World, c: &EntityCopy) -> Entity {
+    let mut e = world.spawn(Position(c.position));
+    if let Some(intent) = c.intent {
+        e.insert(PendingIntent(intent));
+    }
+    if let Some(footprint) = c.footprint {
+        e.insert(footprint);
+    }
+    if let Some(ring) = c.key_ring {
+        e.insert(ring);
+    }
+    if let Some(lock) = c.lock {
+        e.insert(lock);
+    }
+    if let Some(key) = c.key {
+        e.insert(key);
+    }
+    if let Some(speed) = c.speed {
+        e.insert(speed);
+    }
+    if let Some(energy) = c.energy {
+        e.insert(energy);
+    }
+    let m = c.markers;
+    if m.player {
+        e.insert(Player);
+    }
+    if m.ai {
+        e.insert(AI);
+    }
+    if m.actor {
+        e.insert(Actor);
+    }
+    if m.blocking {
+        e.insert(Blocking);
+    }
+    if m.door {
+        e.insert(Door);
+    }
+    if m.goal {
+        e.insert(Goal);
+    }
+    if m.trap {
+        e.insert(Trap);
+    }
+    e.id()
+}
+
+fn position(world: &World, copy: Entity) -> GridCoord {
+    world
+        .get::<Position>(copy)
+        .map(|p| p.0)
+        .expect("every copy has a position")
+}
+
+// Steps `copy` may take now; the occupancy must be current
+fn legal_steps(world: &World, copy: Entity) -> Vec<Dir> {
+    let occ = world.resource::<OccupancyIndex>();
+    let rules = world.resource::<ActiveRules>();
+    let movement = *world.resource::<MovementMode>();
+    let footprint = world.get::<Footprint>(copy).copied().unwrap_or_default();
+    let from = position(world, copy);
+    movement
+        .dirs()
+        .iter()
+        .copied()
+        .filter(|&dir| {
+            matches!(
+                check_step(rules.0.as_ref(), occ, movement, copy, footprint, from, dir),
+                MoveCheck::Allow
+            )
+        })
+        .collect()
+}
+
+// The legal step of `hunter` that ends nearest the player, first in `dirs` order on a tie;
+// `Wait` when it does not act in the running phase
+fn closing_step(world: &World, hunter: Entity, player: Entity) -> Intent {
+    let phase = world
+        .get_resource::<TurnPhase>()
+        .copied()
+        .unwrap_or_default();
+    if !acts_in_phase(world.get::<Energy>(hunter), phase) {
+        return Intent::Wait;
+    }
+    let occ = world.resource::<OccupancyIndex>();
+    let from = position(world, hunter);
+    let to = position(world, player);
+    legal_steps(world, hunter)
+        .into_iter()
+        .min_by_key(|&dir| {
+            let (dx, dy) = occ.delta(occ.step(from, dir), to);
+            dx * dx + dy * dy
+        })
+        .map_or(Intent::Wait, Intent::Move)
+}
+
+// `ExtraPhase` of the copy: the hunter closes in again if it has the energy
+fn close_in(world: &mut World) {
+    let Some(Hunt { hunter, player }) = world.get_resource::<Hunt>().copied() else {
+        return;
+    };
+    let chase = closing_step(world, hunter, player);
+    if let Some(mut pending) = world.get_mut::<PendingIntent>(hunter) {
+        pending.0 = chase;
+    }
+}
@@ -0,0 +1,14 @@
+pub mod behaviour;
+pub mod lookahead;
+pub mod perception;
+pub mod targeting;
+pub mod tree;
+pub mod tuning;
+pub mod utility;
+pub use behaviour::*;
+pub use lookahead::*;
+pub use perception::*;
+pub use targeting::*;
+pub use tree::*;
+pub use tuning::*;
+pub use utility::*;
@@ -0,0 +1,46 @@
+use crate::components::{Awareness, Footprint, Perception};
+use crate::grid::{GridCoord, OccupancyIndex, line_of_sight};
+
+/// This turn's `Awareness` for an AI at `position`, from last turn's and where the player
+/// stands. The AI sees the player within `sight` cells when no wall or door is in the way,
+/// and hears them within `hearing` cells through walls, but only while `player_moved`.
+/// A player it loses is looked for at the last-known cell, then searched for around it.
+pub fn perceive(
+    occ: &OccupancyIndex,
+    perception: &Perception,
+    previous: Awareness,
+    position: GridCoord,
+    footprint: Footprint,
+    player: GridCoord,
+    player_moved: bool,
+) -> Awareness {
+    let (dx, dy) = occ.delta(position, player);
+    let within = |range: i32| dx * dx + dy * dy <= range * range;
+    let seen = within(perception.sight) && line_of_sight(occ, position, player);
+    let heard = player_moved && perception.hearing.is_some_and(within);
+    if seen || heard {
+        return Awareness::Detected(player);
+    }
+
+    let searching = |around: GridCoord, turns: u32| match turns {
+        0 => Awareness::Unaware,
+        turns => Awareness::Searching {
+            around,
+            turns_left: turns - 1,
+        },
+    };
+    match previous {
+        Awareness::Detected(last_known) | Awareness::Investigating(last_known) => {
+            let reached = footprint
+                .cells(position)
+                .any(|c| occ.normalize(c) == last_known);
+            if reached {
+                searching(last_known, perception.search_turns)
+            } else {
+                Awareness::Investigating(last_known)
+            }
+        }
+        Awareness::Searching { around, turns_left } => searching(around, turns_left),
+        Awareness::Unaware => Awareness::Unaware,
+    }
+}
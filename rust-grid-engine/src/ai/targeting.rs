@@ -0,0 +1,39 @@
+use crate::components::{TargetMode, Targeting};
+use crate::grid::{Dir, GridCoord, OccupancyIndex};
+
+/// Cell a chaser with `targeting` hunts on `turn`, from where the player stands, the way they
+/// last moved, and where its `Mirror` partner stands (`None` once the partner is gone).
+/// Targets off the edge of the map are pulled back onto it; targets inside walls are kept,
+/// and the chaser gets as close as it can.
+pub fn ghost_target(
+    targeting: &Targeting,
+    turn: u64,
+    occ: &OccupancyIndex,
+    player: GridCoord,
+    player_facing: Dir,
+    partner: Option<GridCoord>,
+) -> GridCoord {
+    let aim = match &targeting.scatter {
+        Some(scatter) if scatter.active(turn) => scatter.corner,
+        _ => match targeting.mode {
+            TargetMode::Player => player,
+            TargetMode::Ahead(n) => ahead_of(player, player_facing, n),
+            TargetMode::Mirror { ahead, .. } => {
+                let pivot = ahead_of(player, player_facing, ahead);
+                match partner {
+                    Some(partner) => {
+                        let (dx, dy) = occ.delta(partner, pivot);
+                        GridCoord::new(pivot.x + dx, pivot.y + dy)
+                    }
+                    None => pivot,
+                }
+            }
+        },
+    };
+    occ.bounds().map_or(aim, |b| b.clamp(aim))
+}
+
+fn ahead_of(c: GridCoord, facing: Dir, n: i32) -> GridCoord {
+    let (dx, dy) = facing.offset();
+    GridCoord::new(c.x + dx * n, c.y + dy * n)
+}
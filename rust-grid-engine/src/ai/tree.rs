@@ -0,0 +1,246 @@
+use anyhow::{Result, bail};
+use bevy::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::ai::behaviour::{
+    AiBehaviour, AiBehaviours, AiContext, Chaser, Coward, Undo, Wanderer, follow_patrol,
+};
+use crate::intents::Intent;
+
+/// Folder `load_behaviour_trees` reads tree files from.
+pub const BEHAVIOUR_TREE_DIR: &str = "assets/ai";
+
+/// A behaviour tree loaded from a data file, run for every AI of `kind`:
+///
+/// ```json
+/// { "kind": "sentry",
+///   "root": { "selector": [
+///     { "sequence": [{ "condition": { "sees_player": 5 } }, { "action": "chase" }] },
+///     { "action": "wait" }
+///   ] } }
+/// ```
+///
+/// The tree is ticked from the root once per turn. The last action to succeed picks the
+/// AI's intent; a tree that runs no action leaves the AI waiting. A node that fails takes
+/// back what its actions did, so a sequence that stops halfway moves nobody.
+#[derive(Debug, Clone, Deserialize)]
+pub struct BehaviourTree {
+    pub kind: String,
+    pub root: BtNode,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BtStatus {
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BtNode {
+    /// Ticks the children in order until one fails; succeeds when none does.
+    Sequence(Vec<BtNode>),
+    /// Ticks the children in order until one succeeds; fails when none does.
+    Selector(Vec<BtNode>),
+    /// Decorator flipping its child's result.
+    Invert(Box<BtNode>),
+    /// Decorator that succeeds whatever its child returns.
+    Succeed(Box<BtNode>),
+    Condition(BtCondition),
+    Action(BtAction),
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BtCondition {
+    /// The player is within this many cells and no wall or door is in the way.
+    SeesPlayer(i32),
+    /// The player is within this many cells, walls or not. Like `sees_player`, fails while
+    /// an AI with `Perception` has not detected them.
+    PlayerWithin(i32),
+    /// Passes with this probability, drawn from the turn RNG.
+    Chance(f64),
+    /// The AI has a patrol route.
+    OnPatrol,
+}
+
+/// What an AI does. Every action but `chase` and `wait` fails when it finds no step to take.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BtAction {
+    /// Plan as the `chaser` kind does.
+    Chase,
+    /// Step away from the player, as the `coward` kind does.
+    Flee,
+    /// A random legal step.
+    Wander,
+    /// Walk towards the next waypoint of the AI's `Patrol`.
+    Patrol,
+    Wait,
+}
+
+impl BtNode {
+    /// Tick this node, storing the intent of each action that succeeds in `intent`. On
+    /// failure `intent` is left alone, the AI's patrol, path cache and plan trace are put
+    /// back as they were, and it leaves cooperative planning if the node had it join.
+    pub fn tick(&self, ctx: &mut AiContext, intent: &mut Option<Intent>) -> BtStatus {
+        let before = Undo::take(ctx);
+        let mut staged = None;
+        let status = self.tick_staged(ctx, &mut staged);
+        match status {
+            BtStatus::Success => {
+                if staged.is_some() {
+                    *intent = staged;
+                }
+            }
+            BtStatus::Failure => before.restore(ctx),
+        }
+        status
+    }
+
+    fn tick_staged(&self, ctx: &mut AiContext, intent: &mut Option<Intent>) -> BtStatus {
+        match self {
+            BtNode::Sequence(children) => {
+                for child in children {
+                    if child.tick(ctx, intent) == BtStatus::Failure {
+                        return BtStatus::Failure;
+                    }
+                }
+                BtStatus::Success
+            }
+            BtNode::Selector(children) => {
+                for child in children {
+                    if child.tick(ctx, intent) == BtStatus::Success {
+                        return BtStatus::Success;
+                    }
+                }
+                BtStatus::Failure
+            }
+            BtNode::Invert(child) => match child.tick(ctx, intent) {
+                BtStatus::Success => BtStatus::Failure,
+                BtStatus::Failure => BtStatus::Success,
+            },
+            BtNode::Succeed(child) => {
+                child.tick(ctx, intent);
+                BtStatus::Success
+            }
+            BtNode::Condition(condition) => status(condition.holds(ctx)),
+            BtNode::Action(action) => match action.run(ctx) {
+                Some(planned) => {
+                    *intent = Some(planned);
+                    BtStatus::Success
+                }
+                None => BtStatus::Failure,
+            },
+        }
+    }
+
+    // Mistakes serde cannot catch
+    fn check(&self) -> Result<()> {
+        match self {
+            BtNode::Sequence(children) | BtNode::Selector(children) => {
+                children.iter().try_for_each(BtNode::check)
+            }
+            BtNode::Invert(child) | BtNode::Succeed(child) => child.check(),
+            BtNode::Condition(BtCondition::Chance(p)) if !(0.0..=1.0).contains(p) => {
+                bail!("chance {p} is outside 0..=1")
+            }
+            BtNode::Condition(BtCondition::SeesPlayer(r) | BtCondition::PlayerWithin(r))
+                if *r < 0 =>
+            {
+                bail!("range {r} is negative")
+            }
+            BtNode::Condition(_) | BtNode::Action(_) => Ok(()),
+        }
+    }
+}
+
+fn status(ok: bool) -> BtStatus {
+    if ok {
+        BtStatus::Success
+    } else {
+        BtStatus::Failure
+    }
+}
+
+impl BtCondition {
+    fn holds(self, ctx: &mut AiContext) -> bool {
+        match self {
+            BtCondition::SeesPlayer(range) => ctx.sees_player(range),
+            BtCondition::PlayerWithin(range) => {
+                let (dx, dy) = ctx.occ.delta(ctx.position, ctx.target);
+                ctx.detects_player() && dx * dx + dy * dy <= range * range
+            }
+            BtCondition::Chance(p) => ctx.rng.gen_bool(p),
+            BtCondition::OnPatrol => ctx.patrol.is_some(),
+        }
+    }
+}
+
+impl BtAction {
+    fn run(self, ctx: &mut AiContext) -> Option<Intent> {
+        let moved = |intent: Intent| (!matches!(intent, Intent::Wait)).then_some(intent);
+        match self {
+            BtAction::Chase => Some(Chaser::default().plan(ctx)),
+            BtAction::Flee => moved(Coward.plan(ctx)),
+            BtAction::Wander => moved(Wanderer.plan(ctx)),
+            BtAction::Patrol => moved(follow_patrol(ctx)),
+            BtAction::Wait => Some(Intent::Wait),
+        }
+    }
+}
+
+impl AiBehaviour for BehaviourTree {
+    fn plan(&self, ctx: &mut AiContext) -> Intent {
+        let mut intent = None;
+        self.root.tick(ctx, &mut intent);
+        intent.unwrap_or(Intent::Wait)
+    }
+}
+
+pub fn load_behaviour_tree(bytes: &[u8]) -> Result<BehaviourTree> {
+    let tree: BehaviourTree = serde_json::from_slice(bytes)?;
+    if tree.kind.is_empty() {
+        bail!("behaviour tree has no kind");
+    }
+    tree.root.check()?;
+    Ok(tree)
+}
+
+/// Startup system: register every tree in `BEHAVIOUR_TREE_DIR` under its kind.
+/// Files that fail to load are skipped with a warning.
+pub fn load_behaviour_trees(mut behaviours: ResMut<AiBehaviours>) {
+    for tree in load_ai_files(BEHAVIOUR_TREE_DIR, "behaviour tree", load_behaviour_tree) {
+        behaviours.register(tree.kind.clone(), tree);
+    }
+}
+
+// Every `.json` file in `dir` that `load` accepts, in file name order so that later files
+// win a shared kind; a missing folder holds none
+pub(crate) fn load_ai_files<T>(dir: &str, what: &str, load: impl Fn(&[u8]) -> Result<T>) -> Vec<T> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.extension().is_some_and(|ext| ext == "json"))
+        .collect();
+    paths.sort();
+    let mut loaded = Vec::new();
+    for path in paths {
+        match fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|b| load(&b))
+        {
+            Ok(value) => {
+                info!("Loaded {what} from {}", path.display());
+                loaded.push(value);
+            }
+            Err(e) => warn!("The {what} in {} failed to load: {e}", path.display()),
+        }
+    }
+    loaded
+}
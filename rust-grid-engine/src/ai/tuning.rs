@@ -0,0 +1,213 @@
+use anyhow::{Result, bail};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+
+use crate::ai::behaviour::CHASER_WANDER_CHANCE;
+use crate::components::AiKind;
+use crate::intents::AI_SEARCH_BUDGET;
+
+/// File `load_ai_tunings` reads per-kind tuning from.
+pub const AI_TUNING_PATH: &str = "assets/ai_tuning.json";
+
+/// How one kind of AI plays, before `Difficulty` scales it. Every field is optional in the
+/// tuning file and defaults to the engine's behaviour before tuning existed.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct AiTuning {
+    /// Chance a chaser takes a random step instead of its planned one.
+    pub randomness: f64,
+    /// Straight-line cells within which the AI goes after the player. Further away it acts as
+    /// if it had not noticed them: chasers wander, guards keep to their route. `None` is no limit.
+    pub aggression_radius: Option<i32>,
+    /// Chance the AI acts at all on a turn; otherwise it waits.
+    pub move_chance: f64,
+    /// Nodes each of its A* searches may expand before settling for the best partial path.
+    pub search_budget: usize,
+}
+
+impl Default for AiTuning {
+    fn default() -> Self {
+        Self {
+            randomness: CHASER_WANDER_CHANCE,
+            aggression_radius: None,
+            move_chance: 1.0,
+            search_budget: AI_SEARCH_BUDGET,
+        }
+    }
+}
+
+/// Global difficulty, picked in the Settings menu. Scales every kind's `AiTuning`.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 3] = [Difficulty::Easy, Difficulty::Normal, Difficulty::Hard];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "Easy",
+            Difficulty::Normal => "Normal",
+            Difficulty::Hard => "Hard",
+        }
+    }
+
+    /// The next setting up, wrapping from `Hard` back to `Easy`.
+    pub fn next(self) -> Self {
+        let i = Self::ALL.iter().position(|&d| d == self).unwrap_or(0);
+        Self::ALL[(i + 1) % Self::ALL.len()]
+    }
+
+    /// The next setting down, wrapping from `Easy` to `Hard`.
+    pub fn previous(self) -> Self {
+        let i = Self::ALL.iter().position(|&d| d == self).unwrap_or(0);
+        Self::ALL[(i + Self::ALL.len() - 1) % Self::ALL.len()]
+    }
+}
+
+/// Factors one difficulty applies to every `AiTuning` field. Chances are clamped to 0..=1.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct TuningScale {
+    pub randomness: f64,
+    pub aggression_radius: f64,
+    pub move_chance: f64,
+    pub search_budget: f64,
+}
+
+impl Default for TuningScale {
+    fn default() -> Self {
+        Self {
+            randomness: 1.0,
+            aggression_radius: 1.0,
+            move_chance: 1.0,
+            search_budget: 1.0,
+        }
+    }
+}
+
+impl TuningScale {
+    pub fn apply(&self, tuning: AiTuning) -> AiTuning {
+        AiTuning {
+            randomness: (tuning.randomness * self.randomness).clamp(0.0, 1.0),
+            aggression_radius: tuning
+                .aggression_radius
+                .map(|r| (r as f64 * self.aggression_radius).round() as i32),
+            move_chance: (tuning.move_chance * self.move_chance).clamp(0.0, 1.0),
+            search_budget: ((tuning.search_budget as f64 * self.search_budget) as usize).max(1),
+        }
+    }
+}
+
+/// Tuning by `kind`, and the scale of each difficulty, from `AI_TUNING_PATH`:
+///
+/// ```json
+/// { "kinds": {
+///     "chaser": { "randomness": 0.1 },
+///     "wanderer": { "move_chance": 0.75, "aggression_radius": 6 } },
+///   "difficulty": {
+///     "easy": { "randomness": 2.0, "move_chance": 0.85, "search_budget": 0.25 } } }
+/// ```
+///
+/// Kinds missing from the file get `AiTuning::default()`. A difficulty listed in the file
+/// replaces its built-in scale; `normal` leaves tuning as written unless the file scales it.
+#[derive(Resource, Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct AiTunings {
+    pub kinds: HashMap<String, AiTuning>,
+    pub difficulty: HashMap<Difficulty, TuningScale>,
+}
+
+impl Default for AiTunings {
+    fn default() -> Self {
+        let easy = TuningScale {
+            randomness: 2.0,
+            aggression_radius: 0.75,
+            move_chance: 0.85,
+            search_budget: 0.25,
+        };
+        let hard = TuningScale {
+            randomness: 0.5,
+            aggression_radius: 1.5,
+            move_chance: 1.0,
+            search_budget: 2.0,
+        };
+        Self {
+            kinds: HashMap::new(),
+            difficulty: HashMap::from([(Difficulty::Easy, easy), (Difficulty::Hard, hard)]),
+        }
+    }
+}
+
+impl AiTunings {
+    /// Tuning for an AI with this kind at `difficulty`.
+    pub fn for_kind(&self, kind: Option<&AiKind>, difficulty: Difficulty) -> AiTuning {
+        let tuning = kind
+            .and_then(|k| self.kinds.get(&k.0))
+            .copied()
+            .unwrap_or_default();
+        match self.difficulty.get(&difficulty) {
+            Some(scale) => scale.apply(tuning),
+            None => tuning,
+        }
+    }
+}
+
+pub fn load_ai_tuning(bytes: &[u8]) -> Result<AiTunings> {
+    let file: AiTunings = serde_json::from_slice(bytes)?;
+    // a file naming no difficulties keeps the built-in scales
+    let mut tunings = AiTunings {
+        kinds: file.kinds,
+        ..AiTunings::default()
+    };
+    tunings.difficulty.extend(file.difficulty);
+
+    for (kind, t) in &tunings.kinds {
+        if !(0.0..=1.0).contains(&t.randomness) {
+            bail!("randomness {} of {kind:?} is outside 0..=1", t.randomness);
+        }
+        if !(0.0..=1.0).contains(&t.move_chance) {
+            bail!("move chance {} of {kind:?} is outside 0..=1", t.move_chance);
+        }
+        if t.aggression_radius.is_some_and(|r| r < 0) {
+            bail!("aggression radius of {kind:?} is negative");
+        }
+        if t.search_budget == 0 {
+            bail!("search budget of {kind:?} is zero");
+        }
+    }
+    for (difficulty, s) in &tunings.difficulty {
+        let factors = [
+            s.randomness,
+            s.aggression_radius,
+            s.move_chance,
+            s.search_budget,
+        ];
+        if factors.iter().any(|f| !f.is_finite() || *f < 0.0) {
+            bail!("{difficulty:?} scales by a negative or non-finite factor");
+        }
+    }
+    Ok(tunings)
+}
+
+/// Startup system: replace the default tuning with `AI_TUNING_PATH`. Without the file every
+/// kind keeps its defaults; a file that fails to load is skipped with a warning.
+pub fn load_ai_tunings(mut tunings: ResMut<AiTunings>) {
+    let Ok(bytes) = fs::read(AI_TUNING_PATH) else {
+        return;
+    };
+    match load_ai_tuning(&bytes) {
+        Ok(loaded) => {
+            info!("Loaded AI tuning from {AI_TUNING_PATH}");
+            *tunings = loaded;
+        }
+        Err(e) => warn!("The AI tuning in {AI_TUNING_PATH} failed to load: {e}"),
+    }
+}
@@ -0,0 +1,315 @@
+use anyhow::{Result, bail};
+use bevy::prelude::*;
+use rand::Rng;
+use serde::Deserialize;
+
+use crate::ai::behaviour::{AiBehaviour, AiBehaviours, AiContext, Chaser, Coward, Undo, Wanderer};
+use crate::ai::tree::load_ai_files;
+use crate::grid::GridCoord;
+use crate::intents::Intent;
+use crate::pathfinding::stats::PlanSource;
+
+/// Folder `load_utility_ais` reads utility AI files from.
+pub const UTILITY_AI_DIR: &str = "assets/ai/utility";
+
+/// An AI that scores every option each turn and takes the best one:
+///
+/// ```json
+/// { "kind": "hunter",
+///   "options": [
+///     { "action": "chase", "considerations": [
+///         { "input": { "player_distance": 12 }, "curve": { "invert": "identity" } }
+///     ] },
+///     { "action": "wander", "considerations": [
+///         { "input": "random", "curve": { "step": { "threshold": 0.9 } } }
+///     ] },
+///     { "action": "wait", "weight": 0.05 }
+///   ] }
+/// ```
+///
+/// An option scores its `weight` times the product of its considerations. Options are tried
+/// best first, ties in file order, until one finds something to do; the AI waits when none does.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UtilityAi {
+    pub kind: String,
+    pub options: Vec<UtilityOption>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct UtilityOption {
+    pub action: UtilityAction,
+    #[serde(default = "full_weight")]
+    pub weight: f32,
+    #[serde(default)]
+    pub considerations: Vec<Consideration>,
+}
+
+fn full_weight() -> f32 {
+    1.0
+}
+
+/// One input read from the world, mapped onto 0..=1 by a response curve.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Consideration {
+    pub input: ConsiderationInput,
+    #[serde(default)]
+    pub curve: ResponseCurve,
+}
+
+/// World readings, each scaled to 0..=1. Distances are in cells and divided by the given
+/// maximum; a goal or item that does not exist, or a player an AI with `Perception` has not
+/// detected, counts as the maximum away.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConsiderationInput {
+    /// How far the player is.
+    PlayerDistance(f32),
+    /// 1 when the player is within this many cells with nothing blocking the view, else 0.
+    SeesPlayer(i32),
+    /// How far the nearest goal is.
+    GoalDistance(f32),
+    /// How far the player is from the goal nearest to them.
+    PlayerGoalDistance(f32),
+    /// How far the nearest key lying on the map is.
+    ItemDistance(f32),
+    /// A fresh draw from the turn RNG.
+    Random,
+}
+
+/// Maps a 0..=1 input onto a 0..=1 score.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResponseCurve {
+    /// The input as it is.
+    #[default]
+    Identity,
+    Linear {
+        slope: f32,
+        intercept: f32,
+    },
+    /// The input raised to `exponent`: above 1 stays low until the input is high.
+    Power {
+        exponent: f32,
+    },
+    /// An S-curve rising through 0.5 at `midpoint`.
+    Logistic {
+        midpoint: f32,
+        steepness: f32,
+    },
+    /// 1 from `threshold` up, 0 below it.
+    Step {
+        threshold: f32,
+    },
+    /// One minus the inner curve.
+    Invert(Box<ResponseCurve>),
+}
+
+impl ResponseCurve {
+    pub fn eval(&self, x: f32) -> f32 {
+        let x = x.clamp(0.0, 1.0);
+        let y = match self {
+            ResponseCurve::Identity => x,
+            ResponseCurve::Linear { slope, intercept } => slope * x + intercept,
+            ResponseCurve::Power { exponent } => x.powf(*exponent),
+            ResponseCurve::Logistic {
+                midpoint,
+                steepness,
+            } => 1.0 / (1.0 + (-steepness * (x - midpoint)).exp()),
+            ResponseCurve::Step { threshold } => {
+                if x >= *threshold {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            ResponseCurve::Invert(inner) => 1.0 - inner.eval(x),
+        };
+        y.clamp(0.0, 1.0)
+    }
+}
+
+/// What an option does when picked. `flee`, `wander`, `guard_goal` and `pick_up_item` pass
+/// to the next best option when they find nothing to do, and whatever they changed on the AI
+/// (patrol, path cache, plan trace, cooperative planning) is put back first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UtilityAction {
+    /// Plan as the `chaser` kind does, without its random steps.
+    Chase,
+    /// Step away from the player, as the `coward` kind does.
+    Flee,
+    Wait,
+    /// A random legal step.
+    Wander,
+    /// Head for the nearest goal and hold next to it.
+    GuardGoal,
+    /// Head for the nearest key lying on the map. Only AIs with a `KeyRing` can carry keys,
+    /// so for any other AI this always passes.
+    PickUpItem,
+}
+
+// Straight-line distance in cells, honouring wrap-around
+fn distance(ctx: &AiContext, from: GridCoord, to: GridCoord) -> f32 {
+    let (dx, dy) = ctx.occ.delta(from, to);
+    ((dx * dx + dy * dy) as f32).sqrt()
+}
+
+// Closest of `cells` to `from`, ties going to the lowest coordinate so the pick never
+// depends on iteration order
+fn nearest(
+    ctx: &AiContext,
+    from: GridCoord,
+    cells: impl Iterator<Item = GridCoord>,
+) -> Option<GridCoord> {
+    cells.min_by(|&a, &b| {
+        distance(ctx, from, a)
+            .total_cmp(&distance(ctx, from, b))
+            .then((a.x, a.y).cmp(&(b.x, b.y)))
+    })
+}
+
+fn nearest_goal(ctx: &AiContext, from: GridCoord) -> Option<GridCoord> {
+    nearest(ctx, from, ctx.occ.goals().iter().copied())
+}
+
+fn nearest_item(ctx: &AiContext) -> Option<GridCoord> {
+    nearest(ctx, ctx.position, ctx.occ.key_layout().keys.keys().copied())
+}
+
+impl ConsiderationInput {
+    fn read(self, ctx: &mut AiContext) -> f32 {
+        let scaled = |to: Option<GridCoord>, from: GridCoord, max: f32, ctx: &AiContext| {
+            to.map_or(1.0, |to| distance(ctx, from, to) / max)
+        };
+        match self {
+            ConsiderationInput::PlayerDistance(max) => {
+                let player = ctx.detects_player().then_some(ctx.target);
+                scaled(player, ctx.position, max, ctx)
+            }
+            ConsiderationInput::SeesPlayer(range) => {
+                if ctx.sees_player(range) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            ConsiderationInput::GoalDistance(max) => {
+                scaled(nearest_goal(ctx, ctx.position), ctx.position, max, ctx)
+            }
+            ConsiderationInput::PlayerGoalDistance(max) => {
+                let goal = nearest_goal(ctx, ctx.target).filter(|_| ctx.detects_player());
+                scaled(goal, ctx.target, max, ctx)
+            }
+            ConsiderationInput::ItemDistance(max) => {
+                scaled(nearest_item(ctx), ctx.position, max, ctx)
+            }
+            ConsiderationInput::Random => ctx.rng.r#gen::<f32>(),
+        }
+    }
+}
+
+impl UtilityOption {
+    pub fn score(&self, ctx: &mut AiContext) -> f32 {
+        // every consideration is read, so random inputs draw the same amount each turn
+        self.considerations.iter().fold(self.weight, |score, c| {
+            score * c.curve.eval(c.input.read(ctx))
+        })
+    }
+}
+
+impl UtilityAction {
+    fn run(self, ctx: &mut AiContext) -> Option<Intent> {
+        let moved = |intent: Intent| (!matches!(intent, Intent::Wait)).then_some(intent);
+        match self {
+            UtilityAction::Chase => Some(
+                Chaser {
+                    wander_chance: Some(0.0),
+                }
+                .plan(ctx),
+            ),
+            UtilityAction::Flee => moved(Coward.plan(ctx)),
+            UtilityAction::Wait => Some(Intent::Wait),
+            UtilityAction::Wander => moved(Wanderer.plan(ctx)),
+            UtilityAction::GuardGoal => {
+                let goal = nearest_goal(ctx, ctx.position)?;
+                let (dx, dy) = ctx.occ.delta(ctx.position, goal);
+                if dx.abs() <= 1 && dy.abs() <= 1 {
+                    return Some(Intent::Wait);
+                }
+                moved(ctx.walk_to(goal, PlanSource::Guard))
+            }
+            UtilityAction::PickUpItem => {
+                ctx.occ.key_ring(ctx.entity)?;
+                let item = nearest_item(ctx)?;
+                moved(ctx.walk_to(item, PlanSource::Fetch))
+            }
+        }
+    }
+}
+
+impl UtilityAi {
+    /// Every option's score this turn, in file order.
+    pub fn scores(&self, ctx: &mut AiContext) -> Vec<f32> {
+        self.options.iter().map(|o| o.score(ctx)).collect()
+    }
+}
+
+impl AiBehaviour for UtilityAi {
+    fn plan(&self, ctx: &mut AiContext) -> Intent {
+        let scores = self.scores(ctx);
+        let mut ranked: Vec<usize> = (0..self.options.len()).collect();
+        // stable, so equal scores keep file order
+        ranked.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+        for i in ranked.into_iter().filter(|&i| scores[i] > 0.0) {
+            let undo = Undo::take(ctx);
+            match self.options[i].action.run(ctx) {
+                Some(intent) => return intent,
+                None => undo.restore(ctx),
+            }
+        }
+        Intent::Wait
+    }
+}
+
+pub fn load_utility_ai(bytes: &[u8]) -> Result<UtilityAi> {
+    let ai: UtilityAi = serde_json::from_slice(bytes)?;
+    if ai.kind.is_empty() {
+        bail!("utility AI has no kind");
+    }
+    if ai.options.is_empty() {
+        bail!("utility AI {:?} has no options", ai.kind);
+    }
+    for option in &ai.options {
+        if !(option.weight.is_finite() && option.weight >= 0.0) {
+            bail!(
+                "weight {} of {:?} is not a finite, non-negative number",
+                option.weight,
+                option.action
+            );
+        }
+        for c in &option.considerations {
+            let max = match c.input {
+                ConsiderationInput::PlayerDistance(max)
+                | ConsiderationInput::GoalDistance(max)
+                | ConsiderationInput::PlayerGoalDistance(max)
+                | ConsiderationInput::ItemDistance(max) => max,
+                ConsiderationInput::SeesPlayer(_) | ConsiderationInput::Random => 1.0,
+            };
+            if !max.is_finite() || max <= 0.0 {
+                bail!(
+                    "distance maximum {max} of {:?} is not positive",
+                    option.action
+                );
+            }
+        }
+    }
+    Ok(ai)
+}
+
+/// Startup system, after `load_behaviour_trees`: register every utility AI in
+/// `UTILITY_AI_DIR` under its kind. Files that fail to load are skipped with a warning.
+pub fn load_utility_ais(mut behaviours: ResMut<AiBehaviours>) {
+    for ai in load_ai_files(UTILITY_AI_DIR, "utility AI", load_utility_ai) {
+        behaviours.register(ai.kind.clone(), ai);
+    }
+}
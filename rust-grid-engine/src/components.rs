@@ -1,4 +1,5 @@
-use crate::grid::GridCoord;
+use crate::grid::{Dir, GridCoord};
+use crate::pathfinding::keys::KeySet;
 use bevy::prelude::*;
 use serde::{Deserialize, Serialize};
 
@@ -26,6 +27,279 @@ pub struct Actor;
 #[derive(Component)]
 pub struct Door;
 
+/// On a `Door`: the id of the key that opens it. Doors without one open for any `KeyRing`.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Lock(pub i32);
+
+/// A key lying on the map, picked up by the first actor with a `KeyRing` to step on it.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct Key(pub i32);
+
+/// Keys carried by an actor. Only actors with a key ring can open doors.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct KeyRing(pub KeySet);
+
+/// Size of an entity covering more than one cell. Its `Position` is the bottom-left cell.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Footprint {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Footprint {
+    pub const SINGLE: Self = Self {
+        width: 1,
+        height: 1,
+    };
+
+    pub fn is_single(self) -> bool {
+        self.width <= 1 && self.height <= 1
+    }
+
+    /// Every cell covered when the bottom-left cell is `anchor` (not wrapped).
+    pub fn cells(self, anchor: GridCoord) -> impl Iterator<Item = GridCoord> {
+        (0..self.height.max(1))
+            .flat_map(move |dy| (0..self.width.max(1)).map(move |dx| (dx, dy)))
+            .map(move |(dx, dy)| GridCoord::new(anchor.x + dx, anchor.y + dy))
+    }
+}
+
+impl Default for Footprint {
+    fn default() -> Self {
+        Self::SINGLE
+    }
+}
+
+/// Which `AiBehaviour` an AI runs, looked up in `AiBehaviours`; the `kind` from its `EnemySpec`.
+/// AIs without one chase the player.
+#[derive(Component, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct AiKind(pub String);
+
+/// Direction an actor last moved in (`Right` until it first moves).
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Facing(pub Dir);
+
+impl Default for Facing {
+    fn default() -> Self {
+        Self(Dir::Right)
+    }
+}
+
+/// Order a `Patrol` visits its waypoints in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PatrolMode {
+    /// First to last, then back to the first
+    #[default]
+    Loop,
+    /// First to last, then back the way it came
+    PingPong,
+    /// Any other waypoint, picked with the turn RNG
+    Random,
+}
+
+/// Route a guard walks between waypoints while it has not spotted the player.
+#[derive(Component, Debug, Clone, PartialEq, Eq)]
+pub struct Patrol {
+    pub waypoints: Vec<GridCoord>,
+    pub mode: PatrolMode,
+    /// Index of the waypoint being walked to.
+    pub next: usize,
+    /// Walking the route backwards (`PingPong` only).
+    pub reversed: bool,
+}
+
+impl Patrol {
+    pub fn new(waypoints: Vec<GridCoord>, mode: PatrolMode) -> Self {
+        Self {
+            waypoints,
+            mode,
+            next: 0,
+            reversed: false,
+        }
+    }
+
+    pub fn target(&self) -> Option<GridCoord> {
+        self.waypoints.get(self.next).copied()
+    }
+
+    /// Move on to the waypoint after `next`; `Random` draws it from `rng`.
+    pub fn advance(&mut self, rng: &mut impl rand::Rng) {
+        let n = self.waypoints.len();
+        if n < 2 {
+            return;
+        }
+        self.next = match self.mode {
+            PatrolMode::Loop => (self.next + 1) % n,
+            PatrolMode::PingPong => {
+                let at_end = if self.reversed { 0 } else { n - 1 };
+                if self.next == at_end {
+                    self.reversed = !self.reversed;
+                }
+                if self.reversed {
+                    self.next - 1
+                } else {
+                    self.next + 1
+                }
+            }
+            // skip the current waypoint so the guard always moves on
+            PatrolMode::Random => (self.next + rng.gen_range(1..n)) % n,
+        };
+    }
+}
+
+/// What an AI can notice the player with. AIs without it always know where the player is.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Perception {
+    /// Cells it can see, when no wall or door is in the way.
+    pub sight: i32,
+    /// Cells it hears the player moving across, walls or not.
+    #[serde(default)]
+    pub hearing: Option<i32>,
+    /// Turns it searches around the last-known position before giving up.
+    #[serde(default = "default_search_turns")]
+    pub search_turns: u32,
+}
+
+fn default_search_turns() -> u32 {
+    6
+}
+
+/// Where an AI with `Perception` believes the player is, updated by `plan_ai` every turn.
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Awareness {
+    /// Has not noticed the player, or has given up looking.
+    #[default]
+    Unaware,
+    /// Sees or hears the player, who stands here.
+    Detected(GridCoord),
+    /// Lost the player; heading for the cell they were last detected on.
+    Investigating(GridCoord),
+    /// Reached the last-known cell without finding the player; looks around for a few more turns.
+    Searching { around: GridCoord, turns_left: u32 },
+}
+
+impl Awareness {
+    /// Where the AI believes the player is, if anywhere.
+    pub fn believed_target(self) -> Option<GridCoord> {
+        match self {
+            Awareness::Unaware => None,
+            Awareness::Detected(c) | Awareness::Investigating(c) => Some(c),
+            Awareness::Searching { around, .. } => Some(around),
+        }
+    }
+}
+
+/// Cell a chaser heads for instead of the player's own, after the ghosts of classic maze games.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TargetMode {
+    /// The player's cell.
+    #[default]
+    Player,
+    /// This many cells ahead of the player, the way they last moved.
+    Ahead(i32),
+    /// The cell `ahead` of the player, mirrored through it from where `partner` stands:
+    /// the further the partner is behind the player, the further ahead this chaser aims.
+    Mirror { partner: Entity, ahead: i32 },
+}
+
+/// Turns a chaser spends heading for its `corner` instead: scatter for `schedule[0]` turns,
+/// chase for `schedule[1]`, scatter for `schedule[2]`, and so on, chasing for good once the
+/// schedule runs out.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Scatter {
+    pub corner: GridCoord,
+    pub schedule: Vec<u64>,
+}
+
+impl Scatter {
+    /// True when `turn` falls in a scatter phase.
+    pub fn active(&self, turn: u64) -> bool {
+        let mut start = 0;
+        for (phase, &length) in self.schedule.iter().enumerate() {
+            start += length;
+            if turn < start {
+                return phase % 2 == 0;
+            }
+        }
+        false
+    }
+}
+
+/// How a chaser picks the cell it hunts; see `ghost_target`.
+#[derive(Component, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Targeting {
+    pub mode: TargetMode,
+    pub scatter: Option<Scatter>,
+}
+
+/// Plays the next turns out on a copy of the world before moving and takes the step most likely
+/// to catch the player; see `plan_lookahead`. Overrides the AI's kind while it knows where the
+/// player is.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub struct Lookahead {
+    /// Turns played out after each candidate step.
+    pub depth: u32,
+    /// Games played per candidate step, the player answering at random.
+    #[serde(default = "default_rollouts")]
+    pub rollouts: u32,
+}
+
+fn default_rollouts() -> u32 {
+    16
+}
+
+/// Energy an AI spends on one action; see `Speed`.
+pub const ACTION_COST: u32 = 100;
+
+/// Fastest `Speed` a level may give an enemy: ten actions a turn.
+pub const MAX_SPEED: u32 = 10 * ACTION_COST;
+
+/// How quickly an AI acts. It gains this much `Energy` every turn and acts once for each
+/// `ACTION_COST` it has stored, so `50` moves every other turn and `200` twice a turn.
+/// AIs without it act once per turn.
+#[derive(Component, Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[require(Energy)]
+pub struct Speed(pub u32);
+
+/// Energy an AI with `Speed` has stored, and whether it acts in the running phase of the turn.
+#[derive(Component, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Energy {
+    pub stored: u32,
+    pub acting: bool,
+}
+
+impl Energy {
+    /// Spend one action's worth if enough is stored; returns whether the AI acts.
+    pub fn spend(&mut self) -> bool {
+        self.acting = self.stored >= ACTION_COST;
+        if self.acting {
+            self.stored -= ACTION_COST;
+        }
+        self.acting
+    }
+}
+
+/// Path an AI planned towards `target`, reused on later turns until it goes stale.
+/// `path[0]` is the cell the actor stood on when it last followed the path.
+#[derive(Component, Debug, Clone, Default)]
+pub struct PathCache {
+    pub target: Option<GridCoord>,
+    pub path: Vec<GridCoord>,
+}
+
+impl PathCache {
+    pub fn store(&mut self, target: GridCoord, path: Vec<GridCoord>) {
+        self.target = Some(target);
+        self.path = path;
+    }
+
+    pub fn clear(&mut self) {
+        self.target = None;
+        self.path.clear();
+    }
+}
+
 // Temporary per-turn intent buffer
 #[derive(Component, Debug, Clone)]
 pub struct PendingIntent(pub crate::intents::Intent);
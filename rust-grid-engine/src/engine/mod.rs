@@ -8,15 +8,32 @@ use rand::rngs::OsRng;
 use rand::{SeedableRng, rngs::StdRng};
 use schedule::TurnSystems;
 
+use crate::ai::{
+    AiBehaviours, AiTunings, Difficulty, load_ai_tunings, load_behaviour_trees, load_utility_ais,
+};
 use crate::engine::replay::{
     ActiveReplay, ReplayConfig, ReplayLog, ReplayTickTimer, feed_replay_inputs_system,
     is_replay_active,
 };
-use crate::grid::OccupancyIndex;
+use crate::grid::{MovementMode, OccupancyIndex, RegionMap};
+use crate::pathfinding::astar::SearchAlgorithm;
+use crate::pathfinding::cooperative::CooperativePlanning;
+use crate::pathfinding::hpa::HpaGraph;
+use crate::pathfinding::stats::PathDiagnostics;
 
 #[derive(Resource, Debug, Clone, Copy)]
 pub struct TurnNumber(pub u64);
 
+/// Set when the player commits to an action, waiting included, so `player_has_actions` runs the
+/// turn; `advance_turn` clears it.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PlayerActed(pub bool);
+
+/// Which pass of the current turn is running: `0` is the one every actor moves in, later
+/// passes are the extra actions of AIs with `Speed` to spare; see `run_extra_phases`.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TurnPhase(pub u32);
+
 #[derive(Resource, Debug)]
 pub struct TurnRng(pub StdRng);
 
@@ -28,6 +45,8 @@ impl Plugin for EnginePlugin {
     fn build(&self, app: &mut App) {
         let seed: u64 = OsRng.next_u64();
         app.init_resource::<TurnNumber>()
+            .init_resource::<TurnPhase>()
+            .init_resource::<PlayerActed>()
             .init_resource::<ReplayConfig>()
             .init_resource::<ActiveReplay>()
             .init_resource::<ReplayTickTimer>()
@@ -35,6 +54,15 @@ impl Plugin for EnginePlugin {
             .insert_resource(TurnRng(StdRng::seed_from_u64(seed)))
             .insert_resource(ReplayLog::default()) // input logging / replays
             .insert_resource(OccupancyIndex::default()) // grid occupancy queries
+            .init_resource::<MovementMode>() // 4-way unless the level asks for diagonals
+            .init_resource::<RegionMap>() // rooms / corridors, relabelled when walls change
+            .init_resource::<HpaGraph>() // cluster graph for pathfinding on large levels
+            .init_resource::<CooperativePlanning>() // off unless the level asks for it
+            .init_resource::<SearchAlgorithm>() // A* unless the level asks for jump point search
+            .init_resource::<PathDiagnostics>() // per-search stats for the path overlay (F3)
+            .init_resource::<AiBehaviours>() // what each enemy kind does on its turn
+            .init_resource::<AiTunings>() // per-kind knobs, scaled by the difficulty setting
+            .init_resource::<Difficulty>()
             // Configure deterministic turn pipeline inside Update.
             .configure_sets(
                 Update,
@@ -60,6 +88,8 @@ impl Plugin for EnginePlugin {
                         .run_if(is_replay_active),
                 ),
             )
+            .add_systems(Startup, (load_behaviour_trees, load_utility_ais).chain()) // data-driven AI kinds from assets/ai
+            .add_systems(Startup, load_ai_tunings)
             // Plug the default rules & resolve/commit systems.
             .add_plugins(crate::engine::rules::RulesPlugin);
     }
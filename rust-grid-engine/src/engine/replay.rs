@@ -1,6 +1,7 @@
 use super::TurnNumber;
+use crate::ai::Difficulty;
 use crate::components::{PendingIntent, Player};
-use crate::engine::{RunSeed, TurnRng};
+use crate::engine::{PlayerActed, RunSeed, TurnRng};
 use crate::intents::{InputEvent, Intent};
 use bevy::prelude::*;
 use rand::rngs::StdRng;
@@ -23,6 +24,9 @@ impl Default for ReplayConfig {
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Replay {
     pub seed: u64,
+    /// The difficulty the run was played on; replays saved before it was recorded play on `Normal`.
+    #[serde(default)]
+    pub difficulty: Difficulty,
     pub inputs: Vec<RecordedInput>,
 }
 
@@ -80,9 +84,10 @@ impl ActiveReplay {
 }
 
 impl Replay {
-    pub fn from_log(seed: u64, log: &ReplayLog) -> Self {
+    pub fn from_log(seed: u64, difficulty: Difficulty, log: &ReplayLog) -> Self {
         Replay {
             seed,
+            difficulty,
             inputs: log.0.clone(),
         }
     }
@@ -134,6 +139,7 @@ pub fn feed_replay_inputs_system(
     time: Res<Time>,
     cfg: Res<ReplayConfig>,
     mut tick_timer: ResMut<ReplayTickTimer>,
+    mut acted: ResMut<PlayerActed>,
 ) {
     let current_turn = turn.0;
 
@@ -153,7 +159,7 @@ pub fn feed_replay_inputs_system(
     };
 
     // Only apply if player is idle (prevents multiple actions per turn)
-    if !matches!(pending.0, Intent::Wait) {
+    if acted.0 || !matches!(pending.0, Intent::Wait) {
         return;
     }
 
@@ -163,18 +169,23 @@ pub fn feed_replay_inputs_system(
     // Dispatch all inputs whose `turn` matches the current `TurnNumber`.
     while cursor < inputs.len() && inputs[cursor].turn == current_turn {
         let rec = &inputs[cursor];
-        match &rec.input {
-            InputEvent::Move(dir) => pending.0 = Intent::Move(*dir),
-            InputEvent::Interact => pending.0 = Intent::Interact,
-            _ => {}
-        }
+        pending.0 = match &rec.input {
+            InputEvent::Move(dir) => Intent::Move(*dir),
+            InputEvent::Wait => Intent::Wait,
+            InputEvent::Interact => Intent::Interact,
+        };
+        acted.0 = true;
         cursor += 1;
     }
     active.cursor = cursor;
 }
 
-pub fn save_replay_on_game_over(mut log: ResMut<ReplayLog>, seed: Res<RunSeed>) {
-    let replay = Replay::from_log(seed.0, &log);
+pub fn save_replay_on_game_over(
+    mut log: ResMut<ReplayLog>,
+    seed: Res<RunSeed>,
+    difficulty: Res<Difficulty>,
+) {
+    let replay = Replay::from_log(seed.0, *difficulty, &log);
     let _ = replay.save_to_file("assets/replays/last_run.json").ok();
 
     log.clear();
@@ -186,6 +197,7 @@ pub fn start_replay_mode(
     mut tick_timer: ResMut<ReplayTickTimer>,
     mut rng: ResMut<TurnRng>,
     mut seed: ResMut<RunSeed>,
+    mut difficulty: ResMut<Difficulty>,
 ) {
     // Load the replay file
     if let Ok(replay) = Replay::load_from_file("assets/replays/last_run.json") {
@@ -195,6 +207,8 @@ pub fn start_replay_mode(
         // Restore seed
         seed.0 = replay.seed;
         rng.0 = StdRng::seed_from_u64(replay.seed);
+        // and the difficulty the AIs were tuned for
+        *difficulty = replay.difficulty;
 
         turn.0 = 0;
         active.start(replay);
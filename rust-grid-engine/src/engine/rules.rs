@@ -1,6 +1,7 @@
+use crate::components::Footprint;
 use crate::engine::schedule::TurnSystems;
 use crate::grid::occupancy::OccupancyIndex;
-use crate::grid::{GridCoord, Layer};
+use crate::grid::{Dir, GridCoord, Layer, MovementMode};
 use crate::intents::InputEvent;
 use bevy::prelude::*;
 
@@ -10,6 +11,27 @@ pub struct ReachedGoal(pub Entity);
 pub struct SteppedOnTrap(pub Entity);
 #[derive(Message, Debug, Clone, Copy)]
 pub struct GetCaught(pub Entity);
+
+/// Two actors tried to move into the same cell, or through each other, in one turn, or one
+/// tried to move onto a blocker that was bounced itself. `loser` was bounced to `Intent::Wait`;
+/// see `resolve_conflicts`.
+#[derive(Message, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MoveConflict {
+    pub winner: Entity,
+    pub loser: Entity,
+    pub kind: ConflictKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictKind {
+    /// Both would have covered this cell.
+    SameTarget(GridCoord),
+    /// Each would have stepped onto the other.
+    Swap,
+    /// This cell stays covered by `winner`, a blocker that was bounced itself.
+    Held(GridCoord),
+}
+
 /// Result of checking a move.
 #[derive(Debug, Clone, Copy)]
 pub enum MoveCheck {
@@ -28,7 +50,45 @@ pub trait Rules: Send + Sync + 'static {
     ) -> MoveCheck;
 }
 
-/// Default rules: blocks `Blocking` or closed `Door`; fires `ReachedGoal`/`SteppedOnTrap`.
+/// Check one step of `mover` in `dir`: the movement mode, corner cutting, then `Rules::can_enter`
+/// for every cell the mover's footprint would cover.
+pub fn check_step(
+    rules: &dyn Rules,
+    occ: &OccupancyIndex,
+    movement: MovementMode,
+    mover: Entity,
+    footprint: Footprint,
+    from: GridCoord,
+    dir: Dir,
+) -> MoveCheck {
+    if !movement.allows(dir) {
+        return MoveCheck::Blocked;
+    }
+
+    let can_occupy = |anchor: GridCoord| {
+        footprint.cells(anchor).all(|cell| {
+            let cell = occ.normalize(cell);
+            matches!(rules.can_enter(occ, mover, from, cell), MoveCheck::Allow)
+        })
+    };
+
+    // Without corner cutting both orthogonal cells beside a diagonal must be enterable
+    if let Some((horizontal, vertical)) = dir.components()
+        && !movement.cuts_corners()
+        && !(can_occupy(occ.step(from, horizontal)) && can_occupy(occ.step(from, vertical)))
+    {
+        return MoveCheck::Blocked;
+    }
+
+    if can_occupy(occ.step(from, dir)) {
+        MoveCheck::Allow
+    } else {
+        MoveCheck::Blocked
+    }
+}
+
+/// Default rules: blocks leaving the map, `Blocking`, and any `Door` the mover holds no key for;
+/// fires `ReachedGoal`/`SteppedOnTrap`.
 #[derive(Resource, Default)]
 pub struct DefaultRules;
 
@@ -36,12 +96,22 @@ impl Rules for DefaultRules {
     fn can_enter(
         &self,
         occ: &OccupancyIndex,
-        _mover: Entity,
+        mover: Entity,
         _from: GridCoord,
         to: GridCoord,
     ) -> MoveCheck {
-        // Blockers block
-        if !occ.at(Layer::Blockers, to).is_empty() {
+        // The map edge blocks (wrapping maps never get here with an out-of-bounds cell)
+        if !occ.in_bounds(to) {
+            return MoveCheck::Blocked;
+        }
+
+        // Blockers block, except the mover itself (multi-tile entities overlap their old cells)
+        // and doors the mover's key ring opens
+        if occ
+            .at(Layer::Blockers, to)
+            .iter()
+            .any(|&e| e != mover && !occ.opens_door(mover, to, e))
+        {
             return MoveCheck::Blocked;
         }
 
@@ -61,20 +131,55 @@ impl Plugin for RulesPlugin {
             .add_message::<ReachedGoal>()
             .add_message::<SteppedOnTrap>()
             .add_message::<GetCaught>()
+            .add_message::<MoveConflict>()
             .add_message::<InputEvent>()
             .add_systems(
                 Update,
                 (
+                    super::schedule::grant_energy.in_set(TurnSystems::AiPlan),
                     crate::intents::plan_ai.in_set(TurnSystems::AiPlan),
+                    crate::ai::plan_lookahead.in_set(TurnSystems::AiPlan),
                     crate::grid::rebuild_occupancy.in_set(TurnSystems::Resolve),
                     super::schedule::validate_moves.in_set(TurnSystems::Resolve),
+                    super::schedule::resolve_conflicts.in_set(TurnSystems::Resolve),
                     super::schedule::commit_changes.in_set(TurnSystems::Commit),
                     super::schedule::fire_on_enter_hooks.in_set(TurnSystems::Commit),
+                    super::schedule::pick_up_keys.in_set(TurnSystems::Commit),
+                    super::schedule::run_extra_phases.in_set(TurnSystems::Commit),
+                    super::schedule::advance_turn.in_set(TurnSystems::Commit),
                     super::schedule::cleanup_turn.in_set(TurnSystems::Cleanup),
                 )
                     .chain()
                     .run_if(crate::scenes::in_game_and_not_paused)
                     .run_if(super::schedule::player_has_actions),
+            )
+            // Extra actions of fast AIs; see `run_extra_phases`. Occupancy is rebuilt first so
+            // they plan around where the last phase left everyone.
+            .add_systems(
+                super::schedule::ExtraPhase,
+                (
+                    crate::grid::rebuild_occupancy,
+                    crate::intents::plan_ai,
+                    crate::ai::plan_lookahead,
+                    super::schedule::validate_moves,
+                    super::schedule::resolve_conflicts,
+                    super::schedule::commit_changes,
+                    super::schedule::fire_on_enter_hooks,
+                    super::schedule::pick_up_keys,
+                    super::schedule::cleanup_turn,
+                )
+                    .chain(),
+            )
+            // Keep room labels and the path graph current even between turns so UI / tools can query them.
+            .add_systems(
+                Update,
+                (
+                    crate::grid::regions::rebuild_regions,
+                    crate::pathfinding::hpa::update_hpa_graph,
+                )
+                    .in_set(TurnSystems::AiPlan)
+                    .before(crate::intents::plan_ai)
+                    .run_if(crate::scenes::in_game_and_not_paused),
             );
     }
 }
@@ -1,9 +1,14 @@
-use crate::components::{AI, Actor, Player};
-use crate::components::{Goal, PendingIntent, Position, Trap};
-use crate::engine::TurnNumber;
-use crate::engine::rules::{ActiveRules, GetCaught, MoveCheck, ReachedGoal, SteppedOnTrap};
+use crate::components::{AI, Actor, Blocking, Energy, Player, Speed};
+use crate::components::{Facing, Footprint, Goal, Key, KeyRing, PendingIntent, Position, Trap};
+use crate::engine::rules::{
+    ActiveRules, ConflictKind, GetCaught, MoveCheck, MoveConflict, ReachedGoal, SteppedOnTrap,
+    check_step,
+};
+use crate::engine::{PlayerActed, TurnNumber, TurnPhase};
 use crate::grid::occupancy::OccupancyIndex;
+use crate::grid::{GridCoord, MovementMode};
 use crate::intents::Intent;
+use bevy::ecs::schedule::ScheduleLabel;
 use bevy::prelude::*;
 
 #[derive(SystemSet, Debug, Clone, Copy, PartialEq, Eq, Hash)]
@@ -32,19 +37,64 @@ pub enum TurnStep {
     Cleanup,
 }
 
-/// Validate PendingIntent::Move against Rules.
+/// Schedule `run_extra_phases` runs once per extra pass of a turn: the AiPlan, Resolve and
+/// Commit systems again, for the AIs with energy to spare.
+#[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ExtraPhase;
+
+/// Whether an AI acts in `phase`: AIs with `Energy` when they have spent some on it, the
+/// rest only in the first phase.
+pub fn acts_in_phase(energy: Option<&Energy>, phase: TurnPhase) -> bool {
+    match energy {
+        Some(energy) => energy.acting,
+        None => phase.0 == 0,
+    }
+}
+
+/// AiPlan, before `plan_ai`: every AI with `Speed` gains a turn's energy, and acts in the
+/// first phase if it has an action's worth stored.
+pub fn grant_energy(mut q: Query<(&Speed, &mut Energy)>) {
+    for (speed, mut energy) in &mut q {
+        energy.stored = energy.stored.saturating_add(speed.0);
+        energy.spend();
+    }
+}
+
+/// Commit, after the turn's moves: AIs with an action's worth of energy left act again, in
+/// phases planned, resolved and committed like the first while the player waits, until none
+/// has enough left. Energy is only spent here and in `grant_energy`, so replays reproduce it.
+pub fn run_extra_phases(world: &mut World) {
+    let mut q = world.query::<&mut Energy>();
+    let mut phase = 0;
+    loop {
+        let mut any = false;
+        for mut energy in q.iter_mut(world) {
+            any |= energy.spend();
+        }
+        if !any {
+            break;
+        }
+        phase += 1;
+        world.resource_mut::<TurnPhase>().0 = phase;
+        world.run_schedule(ExtraPhase);
+    }
+    world.resource_mut::<TurnPhase>().0 = 0;
+}
+
+/// Validate PendingIntent::Move against the movement mode and Rules.
 /// Converts illegal moves into Wait, legal ones kept as-is.
 pub fn validate_moves(
     occ: Res<OccupancyIndex>,
     rules: Res<ActiveRules>,
-    mut q: Query<(Entity, &Position, &mut PendingIntent), With<Actor>>,
+    movement: Res<MovementMode>,
+    mut q: Query<(Entity, &Position, Option<&Footprint>, &mut PendingIntent), With<Actor>>,
 ) {
     let occ = &*occ;
 
-    for (e, pos, mut pi) in q.iter_mut() {
+    for (e, pos, footprint, mut pi) in q.iter_mut() {
         if let Intent::Move(dir) = pi.0 {
-            let to = dir.step(pos.0);
-            match rules.0.can_enter(occ, e, pos.0, to) {
+            let footprint = footprint.copied().unwrap_or_default();
+            match check_step(rules.0.as_ref(), occ, *movement, e, footprint, pos.0, dir) {
                 MoveCheck::Allow => { /* keep as is */ }
                 MoveCheck::Blocked => {
                     pi.0 = Intent::Wait;
@@ -54,21 +104,134 @@ pub fn validate_moves(
     }
 }
 
-pub fn commit_changes(
-    mut q: Query<(&mut Position, Option<&mut PendingIntent>, Option<&Player>)>,
-    mut turn: ResMut<TurnNumber>,
+type ResolveQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Position,
+        Option<&'static Footprint>,
+        &'static mut PendingIntent,
+        Has<Player>,
+        Has<Blocking>,
+    ),
+    With<Actor>,
+>;
+
+/// Resolve, after `validate_moves`: settle the moves each checked alone that clash with each
+/// other. Movers are taken player first, then in entity order; one whose new cells overlap those
+/// of a mover already taken, or who would swap places with one, is bounced to `Intent::Wait`.
+/// A blocking mover that is bounced keeps its cells, so every move into them is bounced as
+/// well, and the moves are settled again until no more bounce. A `MoveConflict` is written for
+/// each bounced mover.
+pub fn resolve_conflicts(
+    occ: Res<OccupancyIndex>,
+    mut q: ResolveQuery,
+    mut conflicts: MessageWriter<MoveConflict>,
 ) {
-    let mut player_moved_this_tick = false;
+    let mut movers: Vec<_> = q
+        .iter_mut()
+        .filter(|(_, _, _, pending, ..)| matches!(pending.0, Intent::Move(_)))
+        .collect();
+    movers.sort_by_key(|(entity, .., player, _)| (!*player, *entity));
 
-    for (mut pos, pending_intent, maybe_player) in &mut q {
+    // (entity, cells it leaves, cells it enters, whether it blocks) of every mover, in order
+    let moves: Vec<(Entity, Vec<GridCoord>, Vec<GridCoord>, bool)> = movers
+        .iter()
+        .filter_map(|(entity, pos, footprint, pending, _, blocking)| {
+            let Intent::Move(dir) = pending.0 else {
+                return None;
+            };
+            let footprint = footprint.copied().unwrap_or_default();
+            let cells = |anchor| -> Vec<GridCoord> {
+                footprint.cells(anchor).map(|c| occ.normalize(c)).collect()
+            };
+            Some((
+                *entity,
+                cells(pos.0),
+                cells(occ.step(pos.0, dir)),
+                *blocking,
+            ))
+        })
+        .collect();
+
+    // movers held in place because a bounced blocker still covers cells they would enter
+    let mut held: Vec<(usize, Entity, GridCoord)> = Vec::new();
+    let bounced = loop {
+        // indices into `moves` of every move let through so far
+        let mut taken: Vec<usize> = Vec::new();
+        let mut bounced: Vec<(usize, Entity, ConflictKind)> = Vec::new();
+        for (i, (_, from, to, _)) in moves.iter().enumerate() {
+            if let Some(&(_, holder, cell)) = held.iter().find(|(h, ..)| *h == i) {
+                bounced.push((i, holder, ConflictKind::Held(cell)));
+                continue;
+            }
+            let clash = taken.iter().find_map(|&t| {
+                let (winner, left, entered, _) = &moves[t];
+                if let Some(&cell) = to.iter().find(|c| entered.contains(c)) {
+                    Some((*winner, ConflictKind::SameTarget(cell)))
+                } else if to.iter().any(|c| left.contains(c))
+                    && from.iter().any(|c| entered.contains(c))
+                {
+                    Some((*winner, ConflictKind::Swap))
+                } else {
+                    None
+                }
+            });
+            match clash {
+                Some((winner, kind)) => bounced.push((i, winner, kind)),
+                None => taken.push(i),
+            }
+        }
+
+        let before = held.len();
+        for &t in &taken {
+            let entered = &moves[t].2;
+            let holder = bounced.iter().find_map(|&(b, ..)| {
+                let (loser, left, _, blocking) = &moves[b];
+                let cell = entered.iter().find(|c| left.contains(c))?;
+                blocking.then_some((*loser, *cell))
+            });
+            if let Some((holder, cell)) = holder {
+                held.push((t, holder, cell));
+            }
+        }
+        if held.len() == before {
+            break bounced;
+        }
+    };
+
+    for (i, winner, kind) in bounced {
+        let loser = moves[i].0;
+        if let Some((.., pending, _, _)) = movers.iter_mut().find(|(e, ..)| *e == loser) {
+            pending.0 = Intent::Wait;
+        }
+        conflicts.write(MoveConflict {
+            winner,
+            loser,
+            kind,
+        });
+    }
+}
+
+type CommitQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static mut Position,
+        Option<&'static mut PendingIntent>,
+        Option<&'static mut Facing>,
+    ),
+>;
+
+pub fn commit_changes(occ: Res<OccupancyIndex>, mut q: CommitQuery) {
+    for (mut pos, pending_intent, facing) in &mut q {
         if let Some(mut intent) = pending_intent {
             match intent.0 {
                 Intent::Move(dir) => {
-                    pos.0 = dir.step(pos.0);
-
-                    // Still only count the turn as advanced if the player actually moved.
-                    if maybe_player.is_some() {
-                        player_moved_this_tick = true;
+                    pos.0 = occ.step(pos.0, dir);
+                    if let Some(mut facing) = facing {
+                        facing.0 = dir;
                     }
                 }
                 Intent::Wait => {
@@ -82,11 +245,16 @@ pub fn commit_changes(
             intent.0 = Intent::Wait;
         }
     }
-    if player_moved_this_tick {
-        turn.0 += 1;
-    }
 }
 
+/// Commit, last: every turn the player acts in counts, whether they moved, bumped into a wall,
+/// waited or interacted.
+pub fn advance_turn(mut turn: ResMut<TurnNumber>, mut acted: ResMut<PlayerActed>) {
+    turn.0 += 1;
+    acted.0 = false;
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn fire_on_enter_hooks(
     mut ev_goal: MessageWriter<ReachedGoal>,
     mut ev_trap: MessageWriter<SteppedOnTrap>,
@@ -94,7 +262,8 @@ pub fn fire_on_enter_hooks(
     q_goal: Query<&Position, With<Goal>>,
     q_trap: Query<&Position, With<Trap>>,
     q_player: Query<(Entity, &Position), With<Player>>,
-    q_ai: Query<&Position, With<AI>>,
+    q_ai: Query<(&Position, Option<&Footprint>), With<AI>>,
+    occ: Res<OccupancyIndex>,
 ) {
     if let Ok((player_ent, player_pos)) = q_player.single() {
         let player_at = player_pos.0;
@@ -117,10 +286,12 @@ pub fn fire_on_enter_hooks(
             ev_trap.write(SteppedOnTrap(player_ent));
         }
 
-        for ai_pos in q_ai.iter() {
-            let dx = (ai_pos.0.x - player_at.x).abs();
-            let dy = (ai_pos.0.y - player_at.y).abs();
-            if dx + dy == 0 {
+        for (ai_pos, footprint) in q_ai.iter() {
+            let footprint = footprint.copied().unwrap_or_default();
+            if footprint
+                .cells(ai_pos.0)
+                .any(|cell| occ.normalize(cell) == player_at)
+            {
                 ev_caught.write(GetCaught(player_ent));
                 break;
             }
@@ -128,18 +299,50 @@ pub fn fire_on_enter_hooks(
     }
 }
 
+/// Actors with a `KeyRing` pick up any key on a cell they cover; the first in entity order wins a shared cell.
+pub fn pick_up_keys(
+    mut commands: Commands,
+    q_keys: Query<(Entity, &Position, &Key)>,
+    mut q_holders: Query<(Entity, &Position, Option<&Footprint>, &mut KeyRing)>,
+    occ: Res<OccupancyIndex>,
+) {
+    let mut holders: Vec<_> = q_holders.iter_mut().collect();
+    holders.sort_by_key(|(e, ..)| *e);
+    let mut keys: Vec<_> = q_keys.iter().collect();
+    for (_, pos, footprint, ring) in &mut holders {
+        let covered: Vec<_> = footprint
+            .copied()
+            .unwrap_or_default()
+            .cells(pos.0)
+            .map(|c| occ.normalize(c))
+            .collect();
+        keys.retain(|&(key_entity, key_pos, key)| {
+            if !covered.contains(&key_pos.0) {
+                return true;
+            }
+            ring.0.insert(key.0);
+            commands.entity(key_entity).despawn();
+            false
+        });
+    }
+}
+
 pub fn cleanup_turn(mut q: Query<&mut PendingIntent>) {
     for mut intent in &mut q {
         intent.0 = Intent::Wait;
     }
 }
 
-pub fn player_has_actions(q_player: Query<&PendingIntent, With<Player>>) -> bool {
+pub fn player_has_actions(
+    acted: Res<PlayerActed>,
+    q_player: Query<&PendingIntent, With<Player>>,
+) -> bool {
     let Ok(pending) = q_player.single() else {
         return false;
     };
     match pending.0 {
         Intent::Move(_) | Intent::Interact => true,
-        Intent::Wait => false,
+        // a wait only counts when the player chose it
+        Intent::Wait => acted.0,
     }
 }
@@ -1,8 +1,13 @@
+use bevy::ecs::system::SystemParam;
 use bevy::prelude::*;
 pub mod occupancy;
+pub mod regions;
+pub mod sight;
 pub mod types;
-use crate::components::{Actor, Blocking, Position};
+use crate::components::{Actor, Blocking, Door, Footprint, Goal, Key, KeyRing, Lock, Position};
 pub use occupancy::OccupancyIndex;
+pub use regions::{Region, RegionId, RegionKind, RegionLink, RegionMap};
+pub use sight::{line_cells, line_of_sight};
 pub use types::*;
 
 #[derive(Resource)]
@@ -29,6 +34,16 @@ impl GridTransform {
         )
     }
 
+    /// World position of the centre of a footprint anchored at `coord`.
+    pub fn footprint_to_world(&self, coord: GridCoord, footprint: Footprint) -> Vec3 {
+        let offset = Vec2::new(
+            (footprint.width.max(1) - 1) as f32,
+            (footprint.height.max(1) - 1) as f32,
+        ) * self.tile_size
+            * 0.5;
+        self.to_world(coord) + offset.extend(0.0)
+    }
+
     pub fn to_grid(&self, w: Vec2) -> GridCoord {
         let x = ((w.x - self.origin.x) / self.tile_size).floor() as i32;
         let y = ((w.y - self.origin.y) / self.tile_size).floor() as i32;
@@ -72,19 +87,80 @@ pub fn in_bounds(c: GridCoord, width: i32, height: i32) -> bool {
     c.x >= 0 && c.x < width && c.y >= 0 && c.y < height
 }
 
+type OccupantQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Position,
+        Option<&'static Footprint>,
+        Has<Blocking>,
+        Has<Actor>,
+        (
+            Has<Door>,
+            Option<&'static Lock>,
+            Option<&'static Key>,
+            Option<&'static KeyRing>,
+            Has<Goal>,
+        ),
+    ),
+>;
+
 // ECS system to rebuild the index each frame / turn
-pub fn rebuild_occupancy(
-    mut occ: ResMut<OccupancyIndex>,
-    q: Query<(Entity, &Position, Option<&Blocking>, Option<&Actor>)>,
-) {
+pub fn rebuild_occupancy(mut occ: ResMut<OccupancyIndex>, q: OccupantQuery) {
     occ.clear();
 
-    for (entity, pos, blocking, actor) in &q {
-        // Put blocking things into Blockers layer
-        if blocking.is_some() {
-            occ.insert(Layer::Blockers, pos.0, entity);
-        } else if actor.is_some() {
-            occ.insert(Layer::Actors, pos.0, entity);
+    for (entity, pos, footprint, blocking, actor, (door, lock, key, ring, goal)) in &q {
+        if door {
+            occ.insert_door(pos.0, entity, lock.map(|l| l.0));
+        }
+        if let Some(key) = key {
+            occ.insert_key(pos.0, key.0);
+        }
+        if let Some(ring) = ring {
+            occ.set_key_ring(entity, ring.0);
         }
+        if goal {
+            occ.insert_goal(pos.0);
+        }
+
+        if blocking && !actor {
+            occ.insert_opaque(pos.0);
+        }
+
+        let layer = if blocking {
+            // Put blocking things into Blockers layer
+            Layer::Blockers
+        } else if actor {
+            Layer::Actors
+        } else {
+            continue;
+        };
+        // Multi-tile entities are registered in every cell they cover
+        for cell in footprint.copied().unwrap_or_default().cells(pos.0) {
+            let cell = occ.normalize(cell);
+            occ.insert(layer, cell, entity);
+        }
+    }
+}
+
+/// Query filter for the static layout: walls and doors are `Blocking` but never actors.
+pub type StaticLayout = (With<Blocking>, Without<Actor>);
+
+type LayoutMoved = Or<(Added<Blocking>, Changed<Position>)>;
+
+/// Detects changes to the static layout (walls and doors, i.e. `Blocking` entities that are not actors)
+/// since the owning system last ran. Systems caching layout-derived data use it to know when to rebuild.
+#[derive(SystemParam)]
+pub struct WallChanges<'w, 's> {
+    changed: Query<'w, 's, (), (StaticLayout, LayoutMoved)>,
+    removed: RemovedComponents<'w, 's, Blocking>,
+}
+
+impl WallChanges<'_, '_> {
+    pub fn any(&mut self) -> bool {
+        // drain the removal reader so one despawn only triggers one rebuild
+        let removed = self.removed.read().count() > 0;
+        removed || !self.changed.is_empty()
     }
 }
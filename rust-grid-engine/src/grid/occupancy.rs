@@ -1,18 +1,66 @@
 use bevy::prelude::*;
 use smallvec::{smallvec, SmallVec};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
-use super::{GridCoord, Layer};
+use super::{Dir, GridBounds, GridCoord, Layer};
+use crate::pathfinding::keys::{KeyLayout, KeySet};
 
 #[derive(Resource, Default, Clone)]
 pub struct OccupancyIndex {
     // For each layer, a map of grid cell to entities present.
     map: HashMap<Layer, HashMap<GridCoord, SmallVec<[Entity; 4]>>>,
+    // Level extents; `None` means the grid is unbounded.
+    bounds: Option<GridBounds>,
+    // Door locks and keys lying on the map, plus the door entity on each door cell.
+    keys: KeyLayout,
+    door_entities: HashMap<GridCoord, Entity>,
+    // Keys carried by each actor that can open doors.
+    key_rings: HashMap<Entity, KeySet>,
+    // Cells holding static layout (walls and doors), which block sight.
+    opaque: HashSet<GridCoord>,
+    // Goal cells, which AIs may guard.
+    goals: Vec<GridCoord>,
 }
 
 impl OccupancyIndex {
+    /// Clears every layer. Bounds belong to the level and survive a rebuild.
     pub fn clear(&mut self) {
         self.map.clear();
+        self.keys = KeyLayout::default();
+        self.door_entities.clear();
+        self.key_rings.clear();
+        self.opaque.clear();
+        self.goals.clear();
+    }
+    pub fn set_bounds(&mut self, bounds: Option<GridBounds>) {
+        self.bounds = bounds;
+    }
+    pub fn bounds(&self) -> Option<GridBounds> {
+        self.bounds
+    }
+    pub fn in_bounds(&self, coord: GridCoord) -> bool {
+        self.bounds.is_none_or(|b| b.contains(coord))
+    }
+    /// Wrap a coord back onto a toroidal map; unchanged otherwise.
+    pub fn normalize(&self, coord: GridCoord) -> GridCoord {
+        match &self.bounds {
+            Some(b) => b.normalize(coord),
+            None => coord,
+        }
+    }
+    /// Neighbouring cell in `dir`, wrapping around the edges on toroidal maps.
+    pub fn step(&self, from: GridCoord, dir: Dir) -> GridCoord {
+        match &self.bounds {
+            Some(b) => dir.step_in(from, b),
+            None => dir.step(from),
+        }
+    }
+    /// Shortest (dx, dy) from `a` to `b`, honouring wrap-around.
+    pub fn delta(&self, a: GridCoord, b: GridCoord) -> (i32, i32) {
+        match &self.bounds {
+            Some(bounds) => bounds.delta(a, b),
+            None => (b.x - a.x, b.y - a.y),
+        }
     }
     pub fn insert(&mut self, layer: Layer, coord: GridCoord, e: Entity) {
         use std::collections::hash_map::Entry;
@@ -24,6 +72,11 @@ impl OccupancyIndex {
             Entry::Occupied(mut o) => o.get_mut().push(e),
         }
     }
+    pub fn remove(&mut self, layer: Layer, coord: GridCoord, e: Entity) {
+        if let Some(cell) = self.map.get_mut(&layer).and_then(|m| m.get_mut(&coord)) {
+            cell.retain(|x| *x != e);
+        }
+    }
     pub fn at(&self, layer: Layer, coord: GridCoord) -> &[Entity] {
         // const/STATIC-friendly empty slice
         static EMPTY: [Entity; 0] = [];
@@ -36,4 +89,44 @@ impl OccupancyIndex {
     pub fn is_occupied(&self, layer: Layer, coord: GridCoord) -> bool {
         !self.at(layer, coord).is_empty()
     }
+    /// Register a door; `lock` is the key it needs, `None` for a plain door.
+    pub fn insert_door(&mut self, coord: GridCoord, door: Entity, lock: Option<i32>) {
+        self.keys.doors.insert(coord, lock);
+        self.door_entities.insert(coord, door);
+    }
+    pub fn insert_key(&mut self, coord: GridCoord, id: i32) {
+        self.keys.keys.insert(coord, id);
+    }
+    pub fn set_key_ring(&mut self, e: Entity, keys: KeySet) {
+        self.key_rings.insert(e, keys);
+    }
+    /// Mark a cell of static layout, which hides whatever stands behind it.
+    pub fn insert_opaque(&mut self, coord: GridCoord) {
+        self.opaque.insert(coord);
+    }
+    pub fn blocks_sight(&self, coord: GridCoord) -> bool {
+        self.opaque.contains(&coord)
+    }
+    pub fn insert_goal(&mut self, coord: GridCoord) {
+        self.goals.push(coord);
+    }
+    /// Goal cells, in the order they were indexed.
+    pub fn goals(&self) -> &[GridCoord] {
+        &self.goals
+    }
+    /// Doors and keys, as `keyed_path` takes them.
+    pub fn key_layout(&self) -> &KeyLayout {
+        &self.keys
+    }
+    /// Keys carried by `e`; `None` when it cannot open doors at all.
+    pub fn key_ring(&self, e: Entity) -> Option<KeySet> {
+        self.key_rings.get(&e).copied()
+    }
+    /// True when `door` is the door on `coord` and `mover` carries what opens it.
+    pub fn opens_door(&self, mover: Entity, coord: GridCoord, door: Entity) -> bool {
+        self.door_entities.get(&coord) == Some(&door)
+            && self
+                .key_ring(mover)
+                .is_some_and(|ring| ring.opens(self.keys.doors[&coord]))
+    }
 }
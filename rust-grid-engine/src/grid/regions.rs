@@ -0,0 +1,317 @@
+use bevy::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use super::{GridBounds, GridCoord, MovementMode, OccupancyIndex, StaticLayout, WallChanges};
+use crate::components::{Door, Position};
+
+pub type RegionId = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RegionKind {
+    /// Open space: every cell is part of some 2x2 block of free cells
+    Room,
+    /// One-cell-wide passage
+    Corridor,
+}
+
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub id: RegionId,
+    pub kind: RegionKind,
+    /// Cells in flood-fill order
+    pub cells: Vec<GridCoord>,
+    /// Connected component this region belongs to, counting doors as passable
+    pub component: usize,
+}
+
+/// A cell where two regions meet: a `Door` entity, or the mouth of a corridor opening into a room.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RegionLink {
+    pub cell: GridCoord,
+    pub a: RegionId,
+    pub b: RegionId,
+    pub is_door: bool,
+}
+
+/// Passable space split into rooms and corridors, rebuilt whenever walls or doors change.
+#[derive(Resource, Debug, Default, Clone)]
+pub struct RegionMap {
+    bounds: Option<GridBounds>,
+    regions: Vec<Region>,
+    links: Vec<RegionLink>,
+    cell_region: HashMap<GridCoord, RegionId>,
+    // component of every passable or door cell
+    cell_component: HashMap<GridCoord, usize>,
+}
+
+impl RegionMap {
+    /// Label the map from its static layout. Doors are not part of any region but join
+    /// the regions on either side. Cells outside `bounds` are ignored. Cells join up by the
+    /// steps `movement` allows, so diagonal gaps link regions on eight-way maps unless the
+    /// mode forbids squeezing past a wall corner.
+    pub fn build(
+        bounds: GridBounds,
+        movement: MovementMode,
+        walls: &HashSet<GridCoord>,
+        doors: &HashSet<GridCoord>,
+    ) -> Self {
+        let at = move |c: GridCoord, dx: i32, dy: i32| {
+            Some(bounds.normalize(GridCoord::new(c.x + dx, c.y + dy)))
+                .filter(|n| bounds.contains(*n))
+        };
+        let neighbours = |c: GridCoord| {
+            movement.dirs().iter().filter_map(move |dir| {
+                let (dx, dy) = dir.offset();
+                let squeezes = dir.is_diagonal()
+                    && !movement.cuts_corners()
+                    && [at(c, dx, 0), at(c, 0, dy)]
+                        .into_iter()
+                        .any(|side| side.is_none_or(|side| walls.contains(&side)));
+                at(c, dx, dy).filter(|_| !squeezes)
+            })
+        };
+        let free = |c: GridCoord| bounds.contains(c) && !walls.contains(&c) && !doors.contains(&c);
+
+        // Scan in row order so ids are stable for the same layout
+        let cells: Vec<GridCoord> = (0..bounds.height)
+            .flat_map(|y| (0..bounds.width).map(move |x| (x, y)))
+            .map(|(x, y)| GridCoord::new(bounds.origin.x + x, bounds.origin.y + y))
+            .collect();
+
+        let in_open_block = |c: GridCoord| {
+            [(-1, -1), (-1, 0), (0, -1), (0, 0)]
+                .into_iter()
+                .any(|(ox, oy)| {
+                    [(0, 0), (1, 0), (0, 1), (1, 1)]
+                        .into_iter()
+                        .all(|(dx, dy)| {
+                            let cell =
+                                bounds.normalize(GridCoord::new(c.x + ox + dx, c.y + oy + dy));
+                            free(cell)
+                        })
+                })
+        };
+        let kind_of: HashMap<GridCoord, RegionKind> = cells
+            .iter()
+            .filter(|c| free(**c))
+            .map(|&c| {
+                let kind = if in_open_block(c) {
+                    RegionKind::Room
+                } else {
+                    RegionKind::Corridor
+                };
+                (c, kind)
+            })
+            .collect();
+
+        // Connected components through free cells and doors
+        let mut cell_component: HashMap<GridCoord, usize> = HashMap::new();
+        let mut component_count = 0;
+        for &c in &cells {
+            if cell_component.contains_key(&c) || !(kind_of.contains_key(&c) || doors.contains(&c))
+            {
+                continue;
+            }
+            flood(c, &mut cell_component, component_count, |n| {
+                neighbours(n)
+                    .filter(|m| kind_of.contains_key(m) || doors.contains(m))
+                    .collect()
+            });
+            component_count += 1;
+        }
+
+        // Regions: flood fill cells of the same kind
+        let mut cell_region: HashMap<GridCoord, RegionId> = HashMap::new();
+        let mut regions = Vec::new();
+        for &c in &cells {
+            let Some(&kind) = kind_of.get(&c) else {
+                continue;
+            };
+            if cell_region.contains_key(&c) {
+                continue;
+            }
+            let id = regions.len();
+            let filled = flood(c, &mut cell_region, id, |n| {
+                neighbours(n)
+                    .filter(|m| kind_of.get(m) == Some(&kind))
+                    .collect()
+            });
+            regions.push(Region {
+                id,
+                kind,
+                cells: filled,
+                component: cell_component[&c],
+            });
+        }
+
+        // Links: door cells joining regions, and corridor cells opening straight into a room
+        let mut links = Vec::new();
+        for &c in &cells {
+            if doors.contains(&c) {
+                let mut touching: Vec<RegionId> = neighbours(c)
+                    .filter_map(|n| cell_region.get(&n).copied())
+                    .collect();
+                touching.sort_unstable();
+                touching.dedup();
+                for (i, &a) in touching.iter().enumerate() {
+                    for &b in &touching[i + 1..] {
+                        links.push(RegionLink {
+                            cell: c,
+                            a,
+                            b,
+                            is_door: true,
+                        });
+                    }
+                }
+            } else if kind_of.get(&c) == Some(&RegionKind::Corridor) {
+                let a = cell_region[&c];
+                let mut rooms: Vec<RegionId> = neighbours(c)
+                    .filter_map(|n| cell_region.get(&n).copied())
+                    .filter(|&b| regions[b].kind == RegionKind::Room)
+                    .collect();
+                rooms.sort_unstable();
+                rooms.dedup();
+                for b in rooms {
+                    links.push(RegionLink {
+                        cell: c,
+                        a,
+                        b,
+                        is_door: false,
+                    });
+                }
+            }
+        }
+
+        Self {
+            bounds: Some(bounds),
+            regions,
+            links,
+            cell_region,
+            cell_component,
+        }
+    }
+
+    pub fn bounds(&self) -> Option<GridBounds> {
+        self.bounds
+    }
+
+    pub fn regions(&self) -> &[Region] {
+        &self.regions
+    }
+
+    pub fn region(&self, id: RegionId) -> Option<&Region> {
+        self.regions.get(id)
+    }
+
+    pub fn links(&self) -> &[RegionLink] {
+        &self.links
+    }
+
+    pub fn region_at(&self, c: GridCoord) -> Option<RegionId> {
+        self.cell_region.get(&c).copied()
+    }
+
+    /// "Which room is the player in": `None` in corridors, doorways and walls.
+    pub fn room_at(&self, c: GridCoord) -> Option<RegionId> {
+        self.region_at(c)
+            .filter(|&id| self.regions[id].kind == RegionKind::Room)
+    }
+
+    /// Regions reachable from `id` through a single link.
+    pub fn adjacent(&self, id: RegionId) -> Vec<RegionId> {
+        let mut out: Vec<RegionId> = self
+            .links
+            .iter()
+            .filter_map(|l| {
+                if l.a == id {
+                    Some(l.b)
+                } else if l.b == id {
+                    Some(l.a)
+                } else {
+                    None
+                }
+            })
+            .collect();
+        out.sort_unstable();
+        out.dedup();
+        out
+    }
+
+    /// True when a walk from `a` to `b` exists, treating doors as open.
+    pub fn connected(&self, a: GridCoord, b: GridCoord) -> bool {
+        match (self.cell_component.get(&a), self.cell_component.get(&b)) {
+            (Some(ca), Some(cb)) => ca == cb,
+            _ => false,
+        }
+    }
+}
+
+fn flood<T: Copy>(
+    start: GridCoord,
+    labels: &mut HashMap<GridCoord, T>,
+    label: T,
+    next: impl Fn(GridCoord) -> Vec<GridCoord>,
+) -> Vec<GridCoord> {
+    let mut filled = vec![start];
+    let mut queue = VecDeque::from([start]);
+    labels.insert(start, label);
+    while let Some(c) = queue.pop_front() {
+        for n in next(c) {
+            if labels.contains_key(&n) {
+                continue;
+            }
+            labels.insert(n, label);
+            filled.push(n);
+            queue.push_back(n);
+        }
+    }
+    filled
+}
+
+/// Fallback extents for levels without bounds: the box around all walls and doors.
+fn enclosing_bounds(cells: impl Iterator<Item = GridCoord>) -> Option<GridBounds> {
+    let (mut min, mut max) = (
+        GridCoord::new(i32::MAX, i32::MAX),
+        GridCoord::new(i32::MIN, i32::MIN),
+    );
+    for c in cells {
+        min = GridCoord::new(min.x.min(c.x), min.y.min(c.y));
+        max = GridCoord::new(max.x.max(c.x), max.y.max(c.y));
+    }
+    (min.x <= max.x).then(|| GridBounds::new(max.x - min.x + 1, max.y - min.y + 1).with_origin(min))
+}
+
+// ECS system: relabel regions when walls, doors, the level bounds or the movement mode change
+pub fn rebuild_regions(
+    mut regions: ResMut<RegionMap>,
+    occ: Res<OccupancyIndex>,
+    movement: Res<MovementMode>,
+    mut changes: WallChanges,
+    q_walls: Query<(&Position, Has<Door>), StaticLayout>,
+    mut built_for: Local<Option<(Option<GridBounds>, MovementMode)>>,
+) {
+    let changed = changes.any();
+    let key = (occ.bounds(), *movement);
+    if !changed && *built_for == Some(key) {
+        return;
+    }
+    *built_for = Some(key);
+
+    let mut walls = HashSet::new();
+    let mut doors = HashSet::new();
+    for (pos, is_door) in &q_walls {
+        if is_door {
+            doors.insert(pos.0);
+        } else {
+            walls.insert(pos.0);
+        }
+    }
+
+    let bounds = occ
+        .bounds()
+        .or_else(|| enclosing_bounds(walls.iter().chain(doors.iter()).copied()));
+    *regions = match bounds {
+        Some(bounds) => RegionMap::build(bounds, *movement, &walls, &doors),
+        None => RegionMap::default(),
+    };
+}
@@ -0,0 +1,38 @@
+use super::{GridCoord, OccupancyIndex};
+
+/// Cells on the Bresenham line from `from` to `to`, both ends included. On toroidal maps the
+/// line takes the short way round and the cells are wrapped back onto the map.
+pub fn line_cells(occ: &OccupancyIndex, from: GridCoord, to: GridCoord) -> Vec<GridCoord> {
+    let (dx, dy) = occ.delta(from, to);
+    let (sx, sy) = (dx.signum(), dy.signum());
+    let (ax, ay) = (dx.abs(), dy.abs());
+
+    let mut cells = Vec::with_capacity((ax.max(ay) + 1) as usize);
+    let (mut x, mut y) = (0, 0);
+    let mut err = ax - ay;
+    loop {
+        cells.push(occ.normalize(GridCoord::new(from.x + x, from.y + y)));
+        if x == dx && y == dy {
+            return cells;
+        }
+        let e2 = 2 * err;
+        if e2 > -ay {
+            err -= ay;
+            x += sx;
+        }
+        if e2 < ax {
+            err += ax;
+            y += sy;
+        }
+    }
+}
+
+/// True when no wall or door stands between `from` and `to`; actors never block sight,
+/// and the two end cells are not checked.
+pub fn line_of_sight(occ: &OccupancyIndex, from: GridCoord, to: GridCoord) -> bool {
+    let cells = line_cells(occ, from, to);
+    cells.len() <= 2
+        || !cells[1..cells.len() - 1]
+            .iter()
+            .any(|&c| occ.blocks_sight(c))
+}
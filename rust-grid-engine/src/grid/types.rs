@@ -14,24 +14,191 @@ impl GridCoord {
     }
 }
 
-/// Direction in 4-neighbour grid
+/// Direction in 8-neighbour grid; diagonals are only used when the `MovementMode` allows them
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum Dir {
     Up,
     Down,
     Left,
     Right,
+    UpLeft,
+    UpRight,
+    DownLeft,
+    DownRight,
 }
 
 impl Dir {
+    pub const CARDINAL: [Dir; 4] = [Dir::Up, Dir::Down, Dir::Left, Dir::Right];
+    pub const ALL: [Dir; 8] = [
+        Dir::Up,
+        Dir::Down,
+        Dir::Left,
+        Dir::Right,
+        Dir::UpLeft,
+        Dir::UpRight,
+        Dir::DownLeft,
+        Dir::DownRight,
+    ];
+
+    pub fn offset(self) -> (i32, i32) {
+        match self {
+            Dir::Up => (0, 1),
+            Dir::Down => (0, -1),
+            Dir::Left => (-1, 0),
+            Dir::Right => (1, 0),
+            Dir::UpLeft => (-1, 1),
+            Dir::UpRight => (1, 1),
+            Dir::DownLeft => (-1, -1),
+            Dir::DownRight => (1, -1),
+        }
+    }
+
+    pub fn from_offset(dx: i32, dy: i32) -> Option<Dir> {
+        Dir::ALL.into_iter().find(|d| d.offset() == (dx, dy))
+    }
+
+    pub fn is_diagonal(self) -> bool {
+        let (dx, dy) = self.offset();
+        dx != 0 && dy != 0
+    }
+
+    pub fn opposite(self) -> Dir {
+        let (dx, dy) = self.offset();
+        Dir::from_offset(-dx, -dy).expect("every direction has an opposite")
+    }
+
+    /// The horizontal and vertical steps making up a diagonal.
+    pub fn components(self) -> Option<(Dir, Dir)> {
+        match self {
+            Dir::UpLeft => Some((Dir::Left, Dir::Up)),
+            Dir::UpRight => Some((Dir::Right, Dir::Up)),
+            Dir::DownLeft => Some((Dir::Left, Dir::Down)),
+            Dir::DownRight => Some((Dir::Right, Dir::Down)),
+            _ => None,
+        }
+    }
+
     pub fn step(self, p: GridCoord) -> GridCoord {
+        let (dx, dy) = self.offset();
+        GridCoord {
+            x: p.x + dx,
+            y: p.y + dy,
+        }
+    }
+
+    /// Like `step`, but wraps around the edges of a toroidal map.
+    pub fn step_in(self, p: GridCoord, bounds: &GridBounds) -> GridCoord {
+        bounds.normalize(self.step(p))
+    }
+}
+
+/// Which directions actors may move in
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MovementMode {
+    /// Orthogonal steps only
+    #[default]
+    FourWay,
+    /// Orthogonal and diagonal steps, diagonals may squeeze past corners
+    EightWay,
+    /// Diagonal steps need both orthogonal cells beside them to be free
+    EightWayNoCornerCut,
+}
+
+impl MovementMode {
+    pub fn dirs(self) -> &'static [Dir] {
         match self {
-            Dir::Up => GridCoord { x: p.x, y: p.y + 1 },
-            Dir::Down => GridCoord { x: p.x, y: p.y - 1 },
-            Dir::Left => GridCoord { x: p.x - 1, y: p.y },
-            Dir::Right => GridCoord { x: p.x + 1, y: p.y },
+            MovementMode::FourWay => &Dir::CARDINAL,
+            MovementMode::EightWay | MovementMode::EightWayNoCornerCut => &Dir::ALL,
         }
     }
+
+    pub fn allows(self, dir: Dir) -> bool {
+        self != MovementMode::FourWay || !dir.is_diagonal()
+    }
+
+    pub fn cuts_corners(self) -> bool {
+        self == MovementMode::EightWay
+    }
+}
+
+/// Playable area of a level: `width` x `height` cells starting at `origin`.
+/// With `wrap` set the map is toroidal and stepping off one edge enters the opposite one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GridBounds {
+    pub origin: GridCoord,
+    pub width: i32,
+    pub height: i32,
+    pub wrap: bool,
+}
+
+impl GridBounds {
+    pub fn new(width: i32, height: i32) -> Self {
+        Self {
+            origin: GridCoord::ZERO,
+            width,
+            height,
+            wrap: false,
+        }
+    }
+
+    pub fn with_origin(mut self, origin: GridCoord) -> Self {
+        self.origin = origin;
+        self
+    }
+
+    pub fn wrapping(mut self, wrap: bool) -> Self {
+        self.wrap = wrap;
+        self
+    }
+
+    pub fn contains(&self, c: GridCoord) -> bool {
+        super::in_bounds(
+            GridCoord::new(c.x - self.origin.x, c.y - self.origin.y),
+            self.width,
+            self.height,
+        )
+    }
+
+    /// Bring a coord back inside the map on wrapping maps; bounded maps leave it untouched.
+    pub fn normalize(&self, c: GridCoord) -> GridCoord {
+        if !self.wrap || self.width <= 0 || self.height <= 0 {
+            return c;
+        }
+        GridCoord {
+            x: self.origin.x + (c.x - self.origin.x).rem_euclid(self.width),
+            y: self.origin.y + (c.y - self.origin.y).rem_euclid(self.height),
+        }
+    }
+
+    /// Nearest cell on the map: wrapped round on wrapping maps, pulled in to the edge otherwise.
+    pub fn clamp(&self, c: GridCoord) -> GridCoord {
+        if self.wrap || self.width <= 0 || self.height <= 0 {
+            return self.normalize(c);
+        }
+        GridCoord {
+            x: c.x.clamp(self.origin.x, self.origin.x + self.width - 1),
+            y: c.y.clamp(self.origin.y, self.origin.y + self.height - 1),
+        }
+    }
+
+    /// Shortest signed offset from `a` to `b`, going across the seam when that is shorter.
+    pub fn delta(&self, a: GridCoord, b: GridCoord) -> (i32, i32) {
+        let dx = b.x - a.x;
+        let dy = b.y - a.y;
+        if !self.wrap {
+            return (dx, dy);
+        }
+        (wrap_axis(dx, self.width), wrap_axis(dy, self.height))
+    }
+}
+
+fn wrap_axis(d: i32, size: i32) -> i32 {
+    if size <= 0 {
+        return d;
+    }
+    let d = d.rem_euclid(size);
+    if d * 2 > size { d - size } else { d }
 }
 
 /// Mapping between grid and world space
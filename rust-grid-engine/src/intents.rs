@@ -1,16 +1,35 @@
-use crate::components::{AI, PendingIntent, Player, Position};
-use crate::engine::TurnNumber;
+use crate::ai::{
+    AiBehaviours, AiContext, AiTunings, Difficulty, SharedFields, ghost_target, perceive,
+};
+use crate::components::{
+    AI, AiKind, Awareness, Energy, Facing, Footprint, PathCache, Patrol, PendingIntent, Perception,
+    Player, Position, TargetMode, Targeting,
+};
 use crate::engine::replay::{ReplayLog};
+use crate::engine::rules::{ActiveRules, MoveCheck, Rules, check_step};
+use crate::engine::schedule::acts_in_phase;
+use crate::engine::{PlayerActed, TurnNumber, TurnPhase};
 use crate::grid::GridCoord;
 use crate::grid::occupancy::OccupancyIndex;
-use crate::grid::{Dir, Layer};
-use crate::pathfinding::astar::{AStarPolicy, astar};
+use crate::grid::{Dir, Layer, MovementMode};
+use crate::pathfinding::astar::{ORTHOGONAL_COST, SearchAlgorithm, path_is_walkable};
+use crate::pathfinding::cooperative::{
+    CooperativePlanning, ReservationTable, cooperative_path, cooperative_path_traced,
+};
+use crate::pathfinding::dijkstra::DijkstraMap;
+use crate::pathfinding::hpa::HpaGraph;
+use crate::pathfinding::policy::{PathPolicy, PolicyExt, RulesPolicy};
+use crate::pathfinding::stats::{PathDiagnostics, PlanSource};
 use bevy::{input::keyboard::KeyCode, prelude::*};
 use rand::Rng;
 use serde::{Deserialize, Serialize};
-use std::sync::Arc;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/// How far the shared chase field floods on levels without bounds.
+pub(crate) const UNBOUNDED_CHASE_COST: i32 = 64 * ORTHOGONAL_COST as i32;
+/// Nodes an AI may expand per A* search before settling for the best partial path.
+pub(crate) const AI_SEARCH_BUDGET: usize = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Intent {
     Move(Dir),
     Wait,
@@ -29,6 +48,8 @@ pub fn gather_player_input(
     mut q_players: Query<&mut PendingIntent, With<Player>>,
     mut replay: ResMut<ReplayLog>,
     turn: ResMut<TurnNumber>,
+    movement: Res<MovementMode>,
+    mut acted: ResMut<PlayerActed>,
 ) {
     // updated
     let input_event = if keyboard.just_pressed(KeyCode::ArrowUp)
@@ -41,10 +62,28 @@ pub fn gather_player_input(
         Some(InputEvent::Move(Dir::Left))
     } else if keyboard.just_pressed(KeyCode::ArrowRight) || keyboard.just_pressed(KeyCode::KeyD) {
         Some(InputEvent::Move(Dir::Right))
+    } else if keyboard.just_pressed(KeyCode::KeyQ) || keyboard.just_pressed(KeyCode::Numpad7) {
+        Some(InputEvent::Move(Dir::UpLeft))
+    } else if keyboard.just_pressed(KeyCode::KeyE) || keyboard.just_pressed(KeyCode::Numpad9) {
+        Some(InputEvent::Move(Dir::UpRight))
+    } else if keyboard.just_pressed(KeyCode::KeyZ) || keyboard.just_pressed(KeyCode::Numpad1) {
+        Some(InputEvent::Move(Dir::DownLeft))
+    } else if keyboard.just_pressed(KeyCode::KeyC) || keyboard.just_pressed(KeyCode::Numpad3) {
+        Some(InputEvent::Move(Dir::DownRight))
+    } else if keyboard.just_pressed(KeyCode::Space) || keyboard.just_pressed(KeyCode::Numpad5) {
+        Some(InputEvent::Wait)
+    } else if keyboard.just_pressed(KeyCode::KeyF) {
+        Some(InputEvent::Interact)
     } else {
         None
     };
 
+    // Diagonal keys do nothing on 4-way levels
+    let input_event = input_event.filter(|event| match event {
+        InputEvent::Move(dir) => movement.allows(*dir),
+        _ => true,
+    });
+
     if let Some(event) = input_event {
         // apply intent to the player
         for mut pending in &mut q_players {
@@ -54,110 +93,359 @@ pub fn gather_player_input(
                 InputEvent::Interact => Intent::Interact,
             };
         }
+        acted.0 = true;
 
         // record input with current turn number
         replay.record(turn.0, event);
     }
 }
 
+type PlannerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Position,
+        Option<&'static Footprint>,
+        Option<&'static AiKind>,
+        Option<&'static Facing>,
+        Option<&'static mut Patrol>,
+        Option<&'static mut PathCache>,
+        &'static mut PendingIntent,
+        (
+            Option<&'static Perception>,
+            Option<&'static mut Awareness>,
+            Option<&'static Targeting>,
+            Option<&'static Energy>,
+        ),
+    ),
+    With<AI>,
+>;
+
+// The player, their intent for this turn (which tells listening AIs whether they moved),
+// and the way they last moved
+type PlayerQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        &'static Position,
+        Option<&'static PendingIntent>,
+        Option<&'static Facing>,
+    ),
+    (With<Player>, Without<AI>),
+>;
+
+/// Plan the move of every AI acting in this phase of the turn with the `AiBehaviour` registered
+/// for its kind. The rest wait.
+#[allow(clippy::too_many_arguments)]
 pub fn plan_ai(
     occ: Res<OccupancyIndex>,
-    q_player: Query<&Position, With<Player>>,
-    mut q_ai: Query<(&Position, &mut PendingIntent), With<AI>>,
+    q_player: PlayerQuery,
+    mut q_ai: PlannerQuery,
+    q_positions: Query<&Position>,
+    turn: Res<TurnNumber>,
+    phase: Res<TurnPhase>,
     mut rng: ResMut<crate::engine::TurnRng>,
+    movement: Res<MovementMode>,
+    hpa: Res<HpaGraph>,
+    rules: Res<ActiveRules>,
+    cooperative: Res<CooperativePlanning>,
+    search: Res<SearchAlgorithm>,
+    mut diagnostics: ResMut<PathDiagnostics>,
+    behaviours: Res<AiBehaviours>,
+    tunings: Res<AiTunings>,
+    difficulty: Res<Difficulty>,
 ) {
-    let Ok(player_pos) = q_player.single() else {
+    // the first phase starts the turn's diagnostics; extra phases add the fast AIs' plans
+    if phase.0 == 0 {
+        diagnostics.plans.clear();
+        diagnostics.chase_field = None;
+    }
+    let Ok((player_pos, player_intent, player_facing)) = q_player.single() else {
         // no player -> AI does nothing
         return;
     };
     let target = player_pos.0;
+    // a moving player can be heard
+    let player_moved = player_intent.is_some_and(|i| matches!(i.0, Intent::Move(_)));
+    let player_facing = player_facing.copied().unwrap_or_default().0;
 
-    // Build a policy from occupancy.
-    // clone minimal data into the closure to satisfy 'static.
-    let occ_clone = occ.clone();
+    let mut fields = SharedFields::default();
+    let mut cooperative_movers: Vec<(Entity, GridCoord, bool)> = Vec::new();
 
-    let policy = AStarPolicy {
-        passable: Arc::new(move |coord: GridCoord| {
-            // Allow the goal tile itself so we can actually reach the player.
-            if coord == target {
-                return true;
+    for (
+        entity,
+        pos,
+        footprint,
+        kind,
+        facing,
+        mut patrol,
+        mut cache,
+        mut pending,
+        (perception, mut awareness, targeting, energy),
+    ) in q_ai.iter_mut()
+    {
+        let start = pos.0;
+        let footprint = footprint.copied().unwrap_or_default();
+        let tuning = tunings.for_kind(kind, *difficulty);
+
+        // AIs with perception act on where they believe the player is, noticed once per turn
+        let awareness = perception.map(|perception| {
+            let previous = awareness.as_deref().copied().unwrap_or_default();
+            if phase.0 > 0 {
+                return previous;
             }
-            occ_clone.at(Layer::Blockers, coord).is_empty()
-                && occ_clone.at(Layer::Actors, coord).is_empty()
-        }),
-        cost: Arc::new(|_from: GridCoord, _to: GridCoord| 1),
-    };
+            let now = perceive(
+                &occ,
+                perception,
+                previous,
+                start,
+                footprint,
+                target,
+                player_moved,
+            );
+            if let Some(stored) = awareness.as_deref_mut() {
+                *stored = now;
+            }
+            now
+        });
+        // beyond its aggression radius an AI that knows where the player is leaves them be
+        let awareness = match tuning.aggression_radius {
+            Some(radius) if awareness.is_none_or(|a| matches!(a, Awareness::Detected(_))) => {
+                let (dx, dy) = occ.delta(start, target);
+                if dx * dx + dy * dy > radius * radius {
+                    Some(Awareness::Unaware)
+                } else {
+                    awareness
+                }
+            }
+            _ => awareness,
+        };
 
-    for (pos, mut pending) in q_ai.iter_mut() {
-        let start = pos.0;
+        if !acts_in_phase(energy, *phase) {
+            continue;
+        }
 
-        if start == target {
+        if footprint
+            .cells(start)
+            .any(|cell| occ.normalize(cell) == target)
+        {
             // Already on player
             pending.0 = Intent::Wait;
             continue;
         }
 
-        // With small probability, take a random legal step (stochastic behavior)
-        if rng.0.gen_bool(0.10) {
-            if let Some(dir) = random_legal_step(start, target, occ.as_ref(), &mut rng.0) {
-                pending.0 = Intent::Move(dir);
-                continue;
-            }
-            // fall through to A* if no legal random step
-        }
-
-        // Otherwise: take the A* optimal next step
-        // Compute full path from AI to player
-        let Some(path) = astar(start, target, &policy) else {
-            // No path found → wait
+        // only draw for AIs that may skip a turn, so untuned levels replay as before
+        if tuning.move_chance < 1.0 && !rng.0.gen_bool(tuning.move_chance) {
             pending.0 = Intent::Wait;
             continue;
+        }
+
+        let mut hunted = match awareness {
+            Some(awareness) => awareness.believed_target().unwrap_or(start),
+            None => target,
         };
+        // chasers with their own targeting aim around a player they know about
+        if let Some(targeting) = targeting
+            && awareness.is_none_or(|a| matches!(a, Awareness::Detected(_)))
+        {
+            let partner = match targeting.mode {
+                TargetMode::Mirror { partner, .. } => q_positions.get(partner).ok().map(|p| p.0),
+                _ => None,
+            };
+            hunted = ghost_target(targeting, turn.0, &occ, hunted, player_facing, partner);
+        }
 
-        // Path is [start, step1, step2, ..., goal]
-        if path.len() < 2 {
-            // nothing to do
-            pending.0 = Intent::Wait;
+        let mut ctx = AiContext {
+            entity,
+            position: start,
+            footprint,
+            facing: facing.map(|f| f.0),
+            target: hunted,
+            awareness,
+            tuning,
+            player: target,
+            occ: &occ,
+            rules: rules.0.as_ref(),
+            movement: *movement,
+            search: *search,
+            rng: &mut rng.0,
+            cache: cache.as_deref_mut(),
+            diagnostics: &mut diagnostics,
+            patrol: patrol.as_deref_mut(),
+            hpa: &hpa,
+            fields: &mut fields,
+            cooperative: cooperative.window.map(|_| &mut cooperative_movers),
+        };
+        pending.0 = behaviours.for_kind(kind).plan(&mut ctx);
+    }
+
+    if let Some(window) = cooperative.window
+        && !cooperative_movers.is_empty()
+    {
+        plan_cooperative(
+            &occ,
+            rules.0.as_ref(),
+            *movement,
+            target,
+            window,
+            &cooperative_movers,
+            &mut q_ai,
+            &mut rng.0,
+            &mut diagnostics,
+        );
+    }
+}
+
+// WHCA*: reserve where every other AI stands now and steps to this turn, then plan the
+// cooperative movers one by one, closest to the target first, each routing around the
+// cells claimed before it. Wandering movers pick their random step among the free cells.
+#[allow(clippy::too_many_arguments)]
+fn plan_cooperative(
+    occ: &OccupancyIndex,
+    rules: &dyn Rules,
+    movement: MovementMode,
+    target: GridCoord,
+    window: u32,
+    movers: &[(Entity, GridCoord, bool)],
+    q_ai: &mut PlannerQuery,
+    rng: &mut impl Rng,
+    diagnostics: &mut PathDiagnostics,
+) {
+    let mut table = ReservationTable::default();
+    for (entity, pos, footprint, _, _, _, _, pending, _) in q_ai.iter() {
+        if movers.iter().any(|&(e, _, _)| e == entity) {
             continue;
         }
+        let footprint = footprint.copied().unwrap_or_default();
+        let next = match pending.0 {
+            Intent::Move(dir) => occ.step(pos.0, dir),
+            _ => pos.0,
+        };
+        for cell in footprint.cells(pos.0) {
+            table.reserve(entity, occ.normalize(cell), 0);
+        }
+        for cell in footprint.cells(next) {
+            table.reserve(entity, occ.normalize(cell), 1);
+        }
+    }
 
-        let next = path[1];
-        let dir = grid_step_to_dir(start, next);
+    // The movers leave the static layout; where they stand is tracked by the table instead
+    let mut layout = occ.clone();
+    for &(entity, start, _) in movers {
+        layout.remove(Layer::Blockers, start, entity);
+        layout.remove(Layer::Actors, start, entity);
+        table.reserve(entity, start, 0);
+    }
+    let policy = RulesPolicy::new(rules, &layout, movement, Entity::PLACEHOLDER);
+    let max_cost = if occ.bounds().is_some() {
+        i32::MAX
+    } else {
+        UNBOUNDED_CHASE_COST
+    };
+    let distance = DijkstraMap::build([target], &policy, max_cost);
 
-        if let Some(dir) = dir {
-            pending.0 = Intent::Move(dir);
-        } else {
-            // Next tile is weird → wait
-            pending.0 = Intent::Wait;
+    let mut order = movers.to_vec();
+    order.sort_by_key(|&(_, c, _)| (distance.get(c).unwrap_or(i32::MAX), c.y, c.x));
+    for (entity, start, wander) in order {
+        let wander_step = wander
+            .then(|| {
+                policy
+                    .neighbours(start)
+                    .into_iter()
+                    .filter(|&n| table.can_enter(entity, n, 0))
+                    .collect::<Vec<_>>()
+            })
+            .filter(|free| !free.is_empty())
+            .map(|free| free[rng.gen_range(0..free.len())]);
+        let mut stats = None;
+        let (path, source) = match wander_step {
+            Some(step) => (vec![start, step], PlanSource::Wander),
+            None => {
+                stats = diagnostics.new_stats();
+                let path = match stats.as_mut() {
+                    Some(stats) => cooperative_path_traced(
+                        entity, start, &policy, &distance, &table, window, stats,
+                    ),
+                    None => cooperative_path(entity, start, &policy, &distance, &table, window),
+                };
+                (path, PlanSource::Cooperative)
+            }
+        };
+        table.reserve_path(entity, &path, window);
+        diagnostics.record_search(entity, source, &path, stats);
+        if let Ok((_, _, _, _, _, _, _, mut pending, _)) = q_ai.get_mut(entity) {
+            pending.0 = path
+                .get(1)
+                .and_then(|&next| grid_step_to_dir(occ, start, next))
+                .map_or(Intent::Wait, Intent::Move);
         }
     }
 }
 
-fn random_legal_step(
+// Next cell of a cached path, or `None` (and the cache cleared) once it is stale: the actor left
+// the path, the target moved somewhere the path does not reach, or a cell along it became blocked.
+pub(crate) fn follow_cached_path(
+    cache: &mut PathCache,
     start: GridCoord,
     target: GridCoord,
-    occ: &OccupancyIndex,
-    rng: &mut rand::rngs::StdRng,
-) -> Option<Dir> {
-    // Candidate dirs in a fixed list
-    let dirs = [Dir::Up, Dir::Down, Dir::Left, Dir::Right];
-
-    // Collect legal moves (you can bias these later)
-    let mut legal: Vec<Dir> = Vec::new();
-    for d in dirs {
-        let next = d.step(start);
+    footprint: Footprint,
+    policy: &dyn PathPolicy,
+) -> Option<GridCoord> {
+    // step past the cell moved onto last turn; a blocked move leaves the actor on `path[0]`
+    if cache.path.get(1) == Some(&start) {
+        cache.path.remove(0);
+    }
+    let on_path = cache.path.first() == Some(&start);
 
-        // let AI step into player tile to "catch"
-        if next == target {
-            legal.push(d);
-            continue;
-        }
+    // a target that moved onto the path itself can be reached by a prefix of it
+    let reaches_target = cache.target == Some(target)
+        || match cache.path.iter().position(|&anchor| {
+            footprint
+                .cells(anchor)
+                .any(|cell| policy.wrap(cell) == Some(target))
+        }) {
+            Some(i) => {
+                cache.path.truncate(i + 1);
+                cache.target = Some(target);
+                true
+            }
+            None => false,
+        };
 
-        let blocked = !occ.at(Layer::Blockers, next).is_empty();
-        if !blocked {
-            legal.push(d);
-        }
+    if on_path
+        && reaches_target
+        && cache.path.len() >= 2
+        && path_is_walkable(&cache.path, footprint, policy)
+    {
+        Some(cache.path[1])
+    } else {
+        cache.clear();
+        None
     }
+}
+
+pub(crate) fn random_legal_step(
+    rules: &dyn Rules,
+    occ: &OccupancyIndex,
+    movement: MovementMode,
+    me: Entity,
+    footprint: Footprint,
+    start: GridCoord,
+    rng: &mut rand::rngs::StdRng,
+) -> Option<Dir> {
+    // Candidate dirs in a fixed list, kept when the rules allow the step
+    let legal: Vec<Dir> = movement
+        .dirs()
+        .iter()
+        .copied()
+        .filter(|&d| {
+            matches!(
+                check_step(rules, occ, movement, me, footprint, start, d),
+                MoveCheck::Allow
+            )
+        })
+        .collect();
 
     if legal.is_empty() {
         None
@@ -167,16 +455,12 @@ fn random_legal_step(
     }
 }
 
-// Helper: convert (start -> next) into Dir
-fn grid_step_to_dir(from: GridCoord, to: GridCoord) -> Option<Dir> {
-    let dx = to.x - from.x;
-    let dy = to.y - from.y;
-
-    match (dx, dy) {
-        (1, 0) => Some(Dir::Right),
-        (-1, 0) => Some(Dir::Left),
-        (0, 1) => Some(Dir::Up),
-        (0, -1) => Some(Dir::Down),
-        _ => None,
-    }
+// Helper: convert (start -> next) into Dir, including steps across a wrapping edge
+pub(crate) fn grid_step_to_dir(
+    occ: &OccupancyIndex,
+    from: GridCoord,
+    to: GridCoord,
+) -> Option<Dir> {
+    let (dx, dy) = occ.delta(from, to);
+    Dir::from_offset(dx, dy)
 }
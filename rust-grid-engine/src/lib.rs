@@ -1,3 +1,4 @@
+pub mod ai;
 pub mod engine;
 pub mod scenes;
 pub mod grid;
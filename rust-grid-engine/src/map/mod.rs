@@ -1,5 +1,7 @@
 pub mod loader;
 pub mod types;
+pub mod validate;
 
 pub use loader::*;
 pub use types::*;
+pub use validate::*;
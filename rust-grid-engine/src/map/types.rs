@@ -1,4 +1,9 @@
-use crate::grid::GridCoord;
+use crate::components::{
+    Footprint, Lookahead, PatrolMode, Perception, Scatter, Speed, TargetMode, Targeting,
+};
+use crate::grid::{GridBounds, GridCoord, MovementMode};
+use crate::pathfinding::astar::SearchAlgorithm;
+use bevy::prelude::Entity;
 use serde::Deserialize;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -9,11 +14,93 @@ pub struct DoorSpec {
     pub key_id: i32,
 }
 
+/// A key lying on the map; it opens every locked door with the same `key_id`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct KeySpec {
+    pub x: i32,
+    pub y: i32,
+    pub key_id: i32,
+}
+
+/// Route for a `"patroller"`: the waypoints to walk between and the order to visit them in.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PatrolSpec {
+    pub waypoints: Vec<GridCoord>,
+    /// "loop" (default), "ping_pong" or "random".
+    #[serde(default)]
+    pub mode: PatrolMode,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct EnemySpec {
     pub x: i32,
     pub y: i32,
     pub kind: String, 
+    /// Cells covered by a large enemy, e.g. `{ "width": 2, "height": 2 }`; (x, y) is its bottom-left cell.
+    #[serde(default)]
+    pub footprint: Option<Footprint>,
+    /// The enemy carries a key ring: it can open doors and pick up keys on its way.
+    #[serde(default)]
+    pub opens_doors: bool,
+    /// Cell a chaser aims for instead of the player's own, and when it scatters to a corner.
+    #[serde(default)]
+    pub targeting: Option<TargetingSpec>,
+    /// Waypoints a `"patroller"` walks until it spots the player.
+    #[serde(default)]
+    pub patrol: Option<PatrolSpec>,
+    /// Sight and hearing ranges, e.g. `{ "sight": 5, "hearing": 2 }`; without them the enemy
+    /// always knows where the player is.
+    #[serde(default)]
+    pub perception: Option<Perception>,
+    /// Plays the next turns out before each move, e.g. `{ "depth": 4, "rollouts": 16 }`: a
+    /// hard-mode enemy that takes the step most likely to catch the player.
+    #[serde(default)]
+    pub lookahead: Option<Lookahead>,
+    /// Energy gained per turn, acting once per 100: `50` moves every other turn, `200` twice a
+    /// turn. Without it the enemy acts once per turn.
+    #[serde(default)]
+    pub speed: Option<Speed>,
+}
+
+/// Where a chaser aims, e.g. `{ "target": { "ahead": 4 }, "scatter": { "corner": { "x": 0, "y": 0 }, "schedule": [7, 20] } }`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct TargetingSpec {
+    #[serde(default)]
+    pub target: TargetSpec,
+    #[serde(default)]
+    pub scatter: Option<Scatter>,
+}
+
+/// `TargetMode` as levels write it: `"player"`, `{ "ahead": 4 }` or
+/// `{ "mirror": { "partner": 0, "ahead": 2 } }`, where `partner` indexes the level's `enemies`.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TargetSpec {
+    #[default]
+    Player,
+    Ahead(i32),
+    Mirror {
+        partner: usize,
+        ahead: i32,
+    },
+}
+
+impl TargetingSpec {
+    /// The `Targeting` component, given the entities spawned for the level's enemies in order.
+    pub fn resolve(&self, enemies: &[Entity]) -> Targeting {
+        let mode = match self.target {
+            TargetSpec::Player => TargetMode::Player,
+            TargetSpec::Ahead(n) => TargetMode::Ahead(n),
+            TargetSpec::Mirror { partner, ahead } => match enemies.get(partner) {
+                Some(&partner) => TargetMode::Mirror { partner, ahead },
+                None => TargetMode::Ahead(ahead),
+            },
+        };
+        Targeting {
+            mode,
+            scatter: self.scatter.clone(),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -22,8 +109,31 @@ pub struct Level {
     pub width: i32,
     pub height: i32,
 
+    /// Bottom-left cell of the map; defaults to (0, 0).
+    #[serde(default)]
+    pub origin: Option<GridCoord>,
+
+    /// Toroidal map: leaving one edge enters the opposite one (Pac-Man style tunnels).
+    #[serde(default)]
+    pub wrap: bool,
+
+    /// "four_way" (default), "eight_way" or "eight_way_no_corner_cut".
+    #[serde(default)]
+    pub movement: MovementMode,
+
     pub seed: Option<u64>,
 
+    /// Enemies reserve their next moves so they pass each other in corridors
+    /// instead of queueing (see `pathfinding::cooperative`).
+    #[serde(default)]
+    pub cooperative: bool,
+
+    /// "a_star" (default) or "jump_point": the search single-tile AIs run for their own paths.
+    /// Jump point search expands far fewer nodes on open maps; use it on levels whose rules
+    /// treat every step of a kind alike.
+    #[serde(default)]
+    pub search: SearchAlgorithm,
+
     pub player_start: GridCoord,
     pub walls: Vec<GridCoord>,
     pub goals: Vec<GridCoord>,
@@ -34,6 +144,23 @@ pub struct Level {
     #[serde(default)]
     pub doors: Vec<DoorSpec>,
 
+    #[serde(default)]
+    pub keys: Vec<KeySpec>,
+
     #[serde(default)]
     pub enemies: Vec<EnemySpec>,
 }
+
+impl Level {
+    pub fn bounds(&self) -> GridBounds {
+        GridBounds::new(self.width, self.height)
+            .with_origin(self.origin.unwrap_or(GridCoord::ZERO))
+            .wrapping(self.wrap)
+    }
+
+    /// Whether the player spawns with a `KeyRing`: only on levels with keys to pick up or
+    /// locked doors. Without one, every door blocks them.
+    pub fn player_has_key_ring(&self) -> bool {
+        !self.keys.is_empty() || self.doors.iter().any(|d| d.locked)
+    }
+}
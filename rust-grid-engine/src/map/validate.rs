@@ -0,0 +1,195 @@
+use super::types::{Level, TargetSpec, TargetingSpec};
+use crate::components::{MAX_SPEED, Speed};
+use crate::grid::{GridBounds, GridCoord, MovementMode, RegionMap};
+use crate::pathfinding::keys::{KeyLayout, KeySet, MAX_KEY_ID, keyed_path};
+use crate::pathfinding::policy::PathPolicy;
+use anyhow::{Result, bail};
+use std::collections::HashSet;
+
+/// Check a level for mistakes the JSON schema cannot catch: walls outside the map, spawns and
+/// patrol waypoints outside the map or inside walls (every cell of a large enemy included), negative perception ranges, lookaheads that
+/// play nothing, speeds outside 1..=`MAX_SPEED`, mirror partners and scatter corners that do not
+/// exist, key ids out of range, and goals that cannot be reached from the player start.
+/// Locked doors only open once their key has been picked up on the way, and no door opens on
+/// levels where the player carries no key ring (see `Level::player_has_key_ring`).
+pub fn validate_level(level: &Level) -> Result<()> {
+    if level.width <= 0 || level.height <= 0 {
+        bail!("level size {}x{} is empty", level.width, level.height);
+    }
+    let bounds = level.bounds();
+
+    let walls: HashSet<GridCoord> = level.walls.iter().copied().collect();
+    let doors: HashSet<GridCoord> = level
+        .doors
+        .iter()
+        .map(|d| GridCoord::new(d.x, d.y))
+        .collect();
+
+    let check_spawn = |what: &str, c: GridCoord| -> Result<()> {
+        if !bounds.contains(c) {
+            bail!("{what} at ({}, {}) is outside the map", c.x, c.y);
+        }
+        if walls.contains(&c) || doors.contains(&c) {
+            bail!("{what} at ({}, {}) is inside a wall or door", c.x, c.y);
+        }
+        Ok(())
+    };
+
+    for w in &level.walls {
+        if !bounds.contains(*w) {
+            bail!("wall at ({}, {}) is outside the map", w.x, w.y);
+        }
+    }
+    check_spawn("player start", level.player_start)?;
+    for g in &level.goals {
+        check_spawn("goal", *g)?;
+    }
+    for (i, e) in level.enemies.iter().enumerate() {
+        // a large enemy needs room for every cell it covers, wrapping round on toroidal maps
+        let footprint = e.footprint.unwrap_or_default();
+        for cell in footprint.cells(GridCoord::new(e.x, e.y)) {
+            check_spawn("enemy", bounds.normalize(cell))?;
+        }
+        if let Some(t) = &e.targeting {
+            check_targeting(level, i, t)?;
+        }
+        for w in e.patrol.iter().flat_map(|p| &p.waypoints) {
+            check_spawn("patrol waypoint", *w)?;
+        }
+        if let Some(p) = e.perception
+            && (p.sight < 0 || p.hearing.is_some_and(|h| h < 0))
+        {
+            bail!(
+                "enemy at ({}, {}) has a negative perception range",
+                e.x,
+                e.y
+            );
+        }
+        if let Some(l) = e.lookahead
+            && (l.depth == 0 || l.rollouts == 0)
+        {
+            bail!(
+                "enemy at ({}, {}) looks ahead no turns or plays no games",
+                e.x,
+                e.y
+            );
+        }
+        if let Some(Speed(speed)) = e.speed
+            && !(1..=MAX_SPEED).contains(&speed)
+        {
+            bail!(
+                "enemy at ({}, {}) has speed {speed}, outside 1..={MAX_SPEED}",
+                e.x,
+                e.y
+            );
+        }
+    }
+    for k in &level.keys {
+        check_spawn("key", GridCoord::new(k.x, k.y))?;
+    }
+
+    let ids = level
+        .doors
+        .iter()
+        .filter(|d| d.locked)
+        .map(|d| d.key_id)
+        .chain(level.keys.iter().map(|k| k.key_id));
+    for id in ids {
+        if !(0..=MAX_KEY_ID).contains(&id) {
+            bail!("key id {id} is outside 0..={MAX_KEY_ID}");
+        }
+    }
+
+    // A player without a key ring opens no door at all: its doors block like walls
+    let ring = level.player_has_key_ring();
+    let shut: HashSet<GridCoord> = if ring {
+        walls.clone()
+    } else {
+        walls.union(&doors).copied().collect()
+    };
+    let layout = KeyLayout {
+        doors: level
+            .doors
+            .iter()
+            .filter(|_| ring)
+            .map(|d| (GridCoord::new(d.x, d.y), d.locked.then_some(d.key_id)))
+            .collect(),
+        keys: level
+            .keys
+            .iter()
+            .map(|k| (GridCoord::new(k.x, k.y), k.key_id))
+            .collect(),
+    };
+    // Goals outside the player's region component are unreachable whatever keys lie about;
+    // only the rest need the search over keys
+    let regions = RegionMap::build(bounds, level.movement, &shut, &doors);
+    let policy = LayoutPolicy {
+        walls: &shut,
+        bounds,
+        movement: level.movement,
+    };
+    for g in &level.goals {
+        if !regions.connected(level.player_start, *g)
+            || keyed_path(level.player_start, *g, KeySet::default(), &layout, &policy).is_none()
+        {
+            bail!(
+                "goal at ({}, {}) is unreachable from the player start",
+                g.x,
+                g.y
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// The level as the player first finds it: walls block, doors are left to the search over keys
+struct LayoutPolicy<'a> {
+    walls: &'a HashSet<GridCoord>,
+    bounds: GridBounds,
+    movement: MovementMode,
+}
+
+impl PathPolicy for LayoutPolicy<'_> {
+    fn passable(&self, c: GridCoord) -> bool {
+        !self.walls.contains(&c)
+    }
+
+    fn bounds(&self) -> Option<GridBounds> {
+        Some(self.bounds)
+    }
+
+    fn movement(&self) -> MovementMode {
+        self.movement
+    }
+}
+
+fn check_targeting(level: &Level, index: usize, targeting: &TargetingSpec) -> Result<()> {
+    let e = &level.enemies[index];
+    if let TargetSpec::Mirror { partner, .. } = targeting.target
+        && (partner == index || partner >= level.enemies.len())
+    {
+        bail!(
+            "enemy at ({}, {}) mirrors enemy {partner}, which is not another enemy",
+            e.x,
+            e.y
+        );
+    }
+    if let Some(scatter) = &targeting.scatter {
+        if !level.bounds().contains(scatter.corner) {
+            bail!(
+                "scatter corner ({}, {}) is outside the map",
+                scatter.corner.x,
+                scatter.corner.y
+            );
+        }
+        if scatter.schedule.contains(&0) {
+            bail!(
+                "scatter schedule of enemy at ({}, {}) has an empty phase",
+                e.x,
+                e.y
+            );
+        }
+    }
+    Ok(())
+}
@@ -1,30 +1,92 @@
-use crate::grid::neighbours_4 as neigh;
-use crate::grid::{GridCoord};
+use crate::components::Footprint;
+use crate::grid::GridCoord;
+use crate::pathfinding::jps::{jps, jump_search};
+use crate::pathfinding::policy::{FootprintPolicy, PathPolicy, PolicyExt};
+use crate::pathfinding::stats::{SearchStats, Trace};
+use bevy::prelude::Resource;
+use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::{BinaryHeap, HashMap};
-use std::sync::Arc;
 
-#[derive(Clone)]
-pub struct AStarPolicy {
-    pub passable: Arc<dyn Fn(GridCoord) -> bool + Send + Sync>,
-    pub cost: Arc<dyn Fn(GridCoord, GridCoord) -> u32 + Send + Sync>,
+/// Cost of an orthogonal step. Costs are in tenths of a tile so diagonals can approximate sqrt(2).
+pub const ORTHOGONAL_COST: u32 = 10;
+/// Cost of a diagonal step.
+pub const DIAGONAL_COST: u32 = 14;
+
+/// Default cost function: `ORTHOGONAL_COST` or `DIAGONAL_COST` depending on the step.
+pub fn step_cost(from: GridCoord, to: GridCoord) -> u32 {
+    if from.x != to.x && from.y != to.y {
+        DIAGONAL_COST
+    } else {
+        ORTHOGONAL_COST
+    }
+}
+
+/// Which search `find_path` and single-tile footprint searches run for a policy. Also a
+/// resource: the search a level's AIs run for their own paths, `AStar` unless the level sets
+/// `"search": "jump_point"`.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchAlgorithm {
+    #[default]
+    AStar,
+    /// Jump Point Search; only valid when every step of a kind costs the same, as with `step_cost`,
+    /// and the rules let a mover onto a cell whichever side it comes from
+    JumpPoint,
 }
 
-//the estimated cost from a to b
-fn manhattan(a: GridCoord, b: GridCoord) -> u32 {
-    (a.x - b.x).abs() as u32 + (a.y - b.y).abs() as u32
+/// Outcome of a search that may stop short of the goal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PathResult {
+    /// Path all the way to the goal.
+    Complete(Vec<GridCoord>),
+    /// Goal unreachable or budget spent: path to the explored node closest to the goal
+    /// by the heuristic. Just `[start]` when nothing better was found.
+    Partial(Vec<GridCoord>),
+}
+
+impl PathResult {
+    pub fn is_complete(&self) -> bool {
+        matches!(self, PathResult::Complete(_))
+    }
+
+    pub fn path(&self) -> &[GridCoord] {
+        match self {
+            PathResult::Complete(path) | PathResult::Partial(path) => path,
+        }
+    }
+
+    pub fn into_path(self) -> Vec<GridCoord> {
+        match self {
+            PathResult::Complete(path) | PathResult::Partial(path) => path,
+        }
+    }
+
+    /// The full path, or `None` if the search stopped short.
+    pub fn complete(self) -> Option<Vec<GridCoord>> {
+        match self {
+            PathResult::Complete(path) => Some(path),
+            PathResult::Partial(_) => None,
+        }
+    }
 }
 
 #[derive(Copy, Clone, Eq, PartialEq)]
-struct Node {
-    pos: GridCoord,
-    f: u32,
-    g: u32,
+pub(crate) struct Node {
+    pub(crate) pos: GridCoord,
+    pub(crate) f: u32,
+    pub(crate) g: u32,
+    pub(crate) h: u32,
 }
-// Min-heap based on f = g + h
+// Min-heap based on f = g + h. Equal f prefers the node nearer the goal (lower h), then the
+// lower coordinate, so equal-cost paths never depend on the heap's internal order.
 impl Ord for Node {
     fn cmp(&self, other: &Self) -> Ordering {
-        other.f.cmp(&self.f)
+        other
+            .f
+            .cmp(&self.f)
+            .then_with(|| other.h.cmp(&self.h))
+            .then_with(|| (other.pos.y, other.pos.x).cmp(&(self.pos.y, self.pos.x)))
     }
 }
 impl PartialOrd for Node {
@@ -33,46 +95,209 @@ impl PartialOrd for Node {
     }
 }
 
-pub fn astar(start: GridCoord, goal: GridCoord, policy: &AStarPolicy) -> Option<Vec<GridCoord>> {
+/// Shortest path from `start` to `goal` using the policy's `algorithm`.
+pub fn find_path(
+    start: GridCoord,
+    goal: GridCoord,
+    policy: &dyn PathPolicy,
+) -> Option<Vec<GridCoord>> {
+    match policy.algorithm() {
+        SearchAlgorithm::AStar => astar(start, goal, policy),
+        SearchAlgorithm::JumpPoint => jps(start, goal, policy),
+    }
+}
+
+pub fn astar(start: GridCoord, goal: GridCoord, policy: &dyn PathPolicy) -> Option<Vec<GridCoord>> {
+    astar_partial(start, goal, policy).complete()
+}
+
+/// Like `astar`, but when the goal is unreachable or `max_expansions` runs out it returns the
+/// path towards the closest explored node instead of nothing.
+pub fn astar_partial(start: GridCoord, goal: GridCoord, policy: &dyn PathPolicy) -> PathResult {
+    astar_search(start, goal, policy, None)
+}
+
+// A* to a single cell, whatever the policy's `algorithm`
+pub(crate) fn astar_search(
+    start: GridCoord,
+    goal: GridCoord,
+    policy: &dyn PathPolicy,
+    stats: Option<&mut SearchStats>,
+) -> PathResult {
+    search(
+        start,
+        |pos| pos == goal,
+        |pos| policy.heuristic(pos, goal),
+        policy,
+        stats,
+    )
+}
+
+/// A* for an entity covering `footprint` cells, anchored at its bottom-left cell.
+/// Every covered cell must be passable; the path (of anchor cells) ends once the footprint covers `goal`.
+/// Single-tile footprints go through `find_path`, honouring the policy's `algorithm`.
+pub fn astar_footprint(
+    start: GridCoord,
+    goal: GridCoord,
+    footprint: Footprint,
+    policy: &dyn PathPolicy,
+) -> Option<Vec<GridCoord>> {
+    if footprint.is_single() {
+        return find_path(start, goal, policy);
+    }
+    astar_footprint_partial(start, goal, footprint, policy).complete()
+}
+
+/// `astar_footprint` returning the best partial path when the goal cannot be reached in budget.
+/// Single-tile footprints run the policy's `algorithm` here too.
+pub fn astar_footprint_partial(
+    start: GridCoord,
+    goal: GridCoord,
+    footprint: Footprint,
+    policy: &dyn PathPolicy,
+) -> PathResult {
+    footprint_search(start, goal, footprint, policy, None)
+}
+
+/// `astar_footprint_partial` that also fills `stats` with what the search did.
+pub fn astar_footprint_traced(
+    start: GridCoord,
+    goal: GridCoord,
+    footprint: Footprint,
+    policy: &dyn PathPolicy,
+    stats: &mut SearchStats,
+) -> PathResult {
+    footprint_search(start, goal, footprint, policy, Some(stats))
+}
+
+fn footprint_search(
+    start: GridCoord,
+    goal: GridCoord,
+    footprint: Footprint,
+    policy: &dyn PathPolicy,
+    stats: Option<&mut SearchStats>,
+) -> PathResult {
+    if footprint.is_single() {
+        return match policy.algorithm() {
+            SearchAlgorithm::AStar => astar_search(start, goal, policy, stats),
+            SearchAlgorithm::JumpPoint => jump_search(start, goal, policy, stats),
+        };
+    }
+
+    let footprint_policy = FootprintPolicy {
+        inner: policy,
+        footprint,
+    };
+    search(
+        start,
+        |anchor| {
+            footprint
+                .cells(anchor)
+                .any(|cell| footprint_policy.wrap(cell) == Some(goal))
+        },
+        |anchor| footprint_policy.footprint_heuristic(anchor, goal, footprint),
+        &footprint_policy,
+        stats,
+    )
+}
+
+/// True while every step of a previously found path is still legal under `policy`:
+/// each anchor after the first is passable for the footprint and reachable from the one before.
+pub fn path_is_walkable(path: &[GridCoord], footprint: Footprint, policy: &dyn PathPolicy) -> bool {
+    let footprint_policy;
+    let policy: &dyn PathPolicy = if footprint.is_single() {
+        policy
+    } else {
+        footprint_policy = FootprintPolicy {
+            inner: policy,
+            footprint,
+        };
+        &footprint_policy
+    };
+    path.windows(2)
+        .all(|w| policy.passable(w[1]) && policy.neighbours(w[0]).contains(&w[1]))
+}
+
+fn search(
+    start: GridCoord,
+    is_goal: impl Fn(GridCoord) -> bool,
+    heuristic: impl Fn(GridCoord) -> u32,
+    policy: &dyn PathPolicy,
+    stats: Option<&mut SearchStats>,
+) -> PathResult {
+    let mut trace = Trace::new(stats);
     let mut open = BinaryHeap::new();
     let mut came: HashMap<GridCoord, GridCoord> = HashMap::new();
     let mut g: HashMap<GridCoord, u32> = HashMap::new();
+    let mut expanded = 0;
+
+    let reconstruct = |end: GridCoord, came: &HashMap<GridCoord, GridCoord>| {
+        let mut path = vec![end];
+        let mut cur = end;
+        while let Some(&p) = came.get(&cur) {
+            path.push(p);
+            cur = p;
+        }
+        path.reverse();
+        path
+    };
 
+    let h = heuristic(start);
     g.insert(start, 0);
     open.push(Node {
         pos: start,
-        f: manhattan(start, goal),
+        f: h,
         g: 0,
+        h,
     });
+    // closest node to the goal so far, for partial results: lowest h, then cheapest
+    let mut best = (h, 0, start);
 
-    while let Some(Node { pos, g: gscore, .. }) = open.pop() {
-        if pos == goal {
-            // reconstruct
-            let mut path = vec![pos];
-            let mut cur = pos;
-            while let Some(&p) = came.get(&cur) {
-                path.push(p);
-                cur = p;
-            }
-            path.reverse();
-            return Some(path);
+    while let Some(Node {
+        pos,
+        g: gscore,
+        h: hscore,
+        ..
+    }) = open.pop()
+    {
+        if gscore > g[&pos] {
+            // stale entry, a cheaper route to `pos` was already expanded
+            continue;
+        }
+        if is_goal(pos) {
+            let path = reconstruct(pos, &came);
+            trace.finish(path.len());
+            return PathResult::Complete(path);
+        }
+        if policy.max_expansions().is_some_and(|max| expanded >= max) {
+            break;
         }
-        for n in neigh(pos) {
-            if !(policy.passable)(n) {
+        expanded += 1;
+        trace.expand(pos);
+        if (hscore, gscore) < (best.0, best.1) {
+            best = (hscore, gscore, pos);
+        }
+
+        for n in policy.neighbours(pos) {
+            if !policy.passable(n) {
                 continue;
             }
-            let tentative = gscore + (policy.cost)(pos, n);
+            let tentative = gscore + policy.cost(pos, n);
             if tentative < *g.get(&n).unwrap_or(&u32::MAX) {
                 came.insert(n, pos);
                 g.insert(n, tentative);
-                let f = tentative + manhattan(n, goal);
+                let h = heuristic(n);
                 open.push(Node {
                     pos: n,
-                    f,
+                    f: tentative + h,
                     g: tentative,
+                    h,
                 });
             }
         }
+        trace.open_len(open.len());
     }
-    None
+    let path = reconstruct(best.2, &came);
+    trace.finish(path.len());
+    PathResult::Partial(path)
 }
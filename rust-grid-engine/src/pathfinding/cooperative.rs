@@ -0,0 +1,197 @@
+use bevy::prelude::*;
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::grid::GridCoord;
+use crate::pathfinding::astar::ORTHOGONAL_COST;
+use crate::pathfinding::dijkstra::DijkstraMap;
+use crate::pathfinding::policy::PathPolicy;
+use crate::pathfinding::stats::{SearchStats, Trace};
+
+/// Turns ahead each AI reserves when cooperative planning is on.
+pub const COOPERATIVE_WINDOW: u32 = 8;
+
+/// Cooperative (WHCA*-style) planning for single-tile AI: movers plan one after another and
+/// reserve the cells they will stand on for the next `window` turns, so later movers route
+/// around them instead of queueing behind them. `None` (the default) plans each AI on its own.
+#[derive(Resource, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CooperativePlanning {
+    pub window: Option<u32>,
+}
+
+/// Space-time cells claimed by movers: who stands on each cell at each turn, turn 0 being now.
+#[derive(Debug, Clone, Default)]
+pub struct ReservationTable {
+    cells: HashMap<(GridCoord, u32), Entity>,
+}
+
+impl ReservationTable {
+    pub fn reserve(&mut self, who: Entity, c: GridCoord, turn: u32) {
+        self.cells.insert((c, turn), who);
+    }
+
+    /// Claim `path[t]` at every turn `t`, then keep holding the last cell up to turn `until`.
+    pub fn reserve_path(&mut self, who: Entity, path: &[GridCoord], until: u32) {
+        for (turn, &c) in path.iter().enumerate() {
+            self.reserve(who, c, turn as u32);
+        }
+        if let Some(&last) = path.last() {
+            for turn in path.len() as u32..=until {
+                self.reserve(who, last, turn);
+            }
+        }
+    }
+
+    /// True when nobody but `who` holds `c` at `turn`.
+    pub fn is_free(&self, who: Entity, c: GridCoord, turn: u32) -> bool {
+        self.cells.get(&(c, turn)).is_none_or(|&e| e == who)
+    }
+
+    /// Moves are checked against the cells taken at the start of a turn, so stepping onto `c`
+    /// during `turn` needs it free both before and after. This also rules out two movers swapping.
+    pub fn can_enter(&self, who: Entity, c: GridCoord, turn: u32) -> bool {
+        self.is_free(who, c, turn) && self.is_free(who, c, turn + 1)
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct Node {
+    pos: GridCoord,
+    turn: u32,
+    f: u32,
+    g: u32,
+    h: u32,
+}
+// Min-heap on f, then h, then the lower coordinate, as in `astar`
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .f
+            .cmp(&self.f)
+            .then_with(|| other.h.cmp(&self.h))
+            .then_with(|| other.turn.cmp(&self.turn))
+            .then_with(|| (other.pos.y, other.pos.x).cmp(&(self.pos.y, self.pos.x)))
+    }
+}
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Space-time A* for `who` looking `window` turns ahead. `distance` is the remaining cost to
+/// the goal ignoring other movers (a `DijkstraMap` flooded from the goal), which guides the
+/// search past the window; cells it does not cover are never entered.
+///
+/// Returns the cell for each turn from now, a repeated cell meaning a wait. The search stops
+/// at the goal or at the end of the window, and gives `[start]` when every move is reserved.
+pub fn cooperative_path(
+    who: Entity,
+    start: GridCoord,
+    policy: &dyn PathPolicy,
+    distance: &DijkstraMap,
+    table: &ReservationTable,
+    window: u32,
+) -> Vec<GridCoord> {
+    space_time_search(who, start, policy, distance, table, window, None)
+}
+
+/// `cooperative_path` that also fills `stats` with what the search did. A cell appears in
+/// `explored` once for every turn it was expanded at.
+pub fn cooperative_path_traced(
+    who: Entity,
+    start: GridCoord,
+    policy: &dyn PathPolicy,
+    distance: &DijkstraMap,
+    table: &ReservationTable,
+    window: u32,
+    stats: &mut SearchStats,
+) -> Vec<GridCoord> {
+    space_time_search(who, start, policy, distance, table, window, Some(stats))
+}
+
+fn space_time_search(
+    who: Entity,
+    start: GridCoord,
+    policy: &dyn PathPolicy,
+    distance: &DijkstraMap,
+    table: &ReservationTable,
+    window: u32,
+    stats: Option<&mut SearchStats>,
+) -> Vec<GridCoord> {
+    let mut trace = Trace::new(stats);
+    let h_of = |c: GridCoord| distance.get(c).map(|d| d.max(0) as u32);
+    let Some(h) = h_of(start) else {
+        trace.finish(1);
+        return vec![start];
+    };
+
+    let mut open = BinaryHeap::new();
+    let mut came: HashMap<(GridCoord, u32), GridCoord> = HashMap::new();
+    let mut g: HashMap<(GridCoord, u32), u32> = HashMap::new();
+    g.insert((start, 0), 0);
+    open.push(Node {
+        pos: start,
+        turn: 0,
+        f: h,
+        g: 0,
+        h,
+    });
+
+    while let Some(Node {
+        pos,
+        turn,
+        g: gscore,
+        h: hscore,
+        ..
+    }) = open.pop()
+    {
+        if gscore > g[&(pos, turn)] {
+            continue;
+        }
+        if hscore == 0 || turn == window {
+            let mut path = vec![pos];
+            let mut cur = pos;
+            for t in (1..=turn).rev() {
+                cur = came[&(cur, t)];
+                path.push(cur);
+            }
+            path.reverse();
+            trace.finish(path.len());
+            return path;
+        }
+        trace.expand(pos);
+
+        // waiting costs a turn like a step does, so movers do not idle while a way is open
+        let moves = policy
+            .neighbours(pos)
+            .into_iter()
+            .filter(|&n| policy.passable(n) && table.can_enter(who, n, turn))
+            .map(|n| (n, policy.cost(pos, n)));
+        let wait = table
+            .is_free(who, pos, turn + 1)
+            .then_some((pos, ORTHOGONAL_COST));
+
+        for (n, cost) in moves.chain(wait) {
+            let Some(h) = h_of(n) else {
+                continue;
+            };
+            let key = (n, turn + 1);
+            let tentative = gscore + cost;
+            if tentative < *g.get(&key).unwrap_or(&u32::MAX) {
+                came.insert(key, pos);
+                g.insert(key, tentative);
+                open.push(Node {
+                    pos: n,
+                    turn: turn + 1,
+                    f: tentative + h,
+                    g: tentative,
+                    h,
+                });
+            }
+        }
+        trace.open_len(open.len());
+    }
+    trace.finish(1);
+    vec![start]
+}
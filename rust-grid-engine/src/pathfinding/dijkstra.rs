@@ -0,0 +1,197 @@
+use crate::grid::GridCoord;
+use crate::pathfinding::policy::{PathPolicy, PolicyExt};
+use crate::pathfinding::stats::{SearchStats, Trace};
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Distance field over the grid: every reachable cell holds the cost of the cheapest walk
+/// to the nearest goal, in the same units as `PathPolicy::cost`.
+///
+/// Movers walk downhill with `next_step`. Values are signed so fields can be combined and
+/// inverted into flee maps, where the goals end up as the highest points.
+#[derive(Debug, Clone, Default)]
+pub struct DijkstraMap {
+    dist: HashMap<GridCoord, i32>,
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct Node {
+    pos: GridCoord,
+    cost: i32,
+}
+// Min-heap on cost; ties resolved by coordinate so fields do not depend on insertion order
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .cost
+            .cmp(&self.cost)
+            .then_with(|| (other.pos.y, other.pos.x).cmp(&(self.pos.y, self.pos.x)))
+    }
+}
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl DijkstraMap {
+    /// Multi-source field: distance from every cell to the nearest of `goals`.
+    /// Cells costing more than `max_cost` are left out, which keeps floods on unbounded maps finite.
+    pub fn build(
+        goals: impl IntoIterator<Item = GridCoord>,
+        policy: &dyn PathPolicy,
+        max_cost: i32,
+    ) -> Self {
+        Self::from_seeds(goals.into_iter().map(|g| (g, 0)), policy, max_cost)
+    }
+
+    /// Field flooded from cells with starting values. Seeds with lower values pull harder,
+    /// so negative seeds mark especially attractive goals.
+    pub fn from_seeds(
+        seeds: impl IntoIterator<Item = (GridCoord, i32)>,
+        policy: &dyn PathPolicy,
+        max_cost: i32,
+    ) -> Self {
+        Self::flood(seeds, policy, max_cost, None)
+    }
+
+    /// `build` that also fills `stats` with what the flood did; `path_len` stays 0.
+    pub fn build_traced(
+        goals: impl IntoIterator<Item = GridCoord>,
+        policy: &dyn PathPolicy,
+        max_cost: i32,
+        stats: &mut SearchStats,
+    ) -> Self {
+        Self::flood(
+            goals.into_iter().map(|g| (g, 0)),
+            policy,
+            max_cost,
+            Some(stats),
+        )
+    }
+
+    fn flood(
+        seeds: impl IntoIterator<Item = (GridCoord, i32)>,
+        policy: &dyn PathPolicy,
+        max_cost: i32,
+        stats: Option<&mut SearchStats>,
+    ) -> Self {
+        let mut trace = Trace::new(stats);
+        let mut dist: HashMap<GridCoord, i32> = HashMap::new();
+        let mut open = BinaryHeap::new();
+        for (pos, cost) in seeds {
+            let Some(pos) = policy.wrap(pos) else {
+                continue;
+            };
+            if cost < *dist.get(&pos).unwrap_or(&i32::MAX) {
+                dist.insert(pos, cost);
+                open.push(Node { pos, cost });
+            }
+        }
+
+        while let Some(Node { pos, cost }) = open.pop() {
+            if cost > dist[&pos] {
+                continue;
+            }
+            trace.expand(pos);
+            // cells that can step into pos: ask each candidate for its own neighbours, so rules
+            // that depend on where a step starts (one-way cells) are read the right way round
+            for n in policy.adjacent(pos) {
+                if !policy.passable(n) || !policy.neighbours(n).contains(&pos) {
+                    continue;
+                }
+                let tentative = cost.saturating_add(policy.cost(n, pos) as i32);
+                if tentative > max_cost {
+                    continue;
+                }
+                if tentative < *dist.get(&n).unwrap_or(&i32::MAX) {
+                    dist.insert(n, tentative);
+                    open.push(Node {
+                        pos: n,
+                        cost: tentative,
+                    });
+                }
+            }
+            trace.open_len(open.len());
+        }
+        trace.finish(0);
+        Self { dist }
+    }
+
+    pub fn get(&self, c: GridCoord) -> Option<i32> {
+        self.dist.get(&c).copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (GridCoord, i32)> + '_ {
+        self.dist.iter().map(|(&c, &d)| (c, d))
+    }
+
+    pub fn len(&self) -> usize {
+        self.dist.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.dist.is_empty()
+    }
+
+    /// Weighted sum of several fields, e.g. "towards the player, but prefer the exits".
+    /// Only cells present in every field are kept.
+    pub fn combine(fields: &[(&DijkstraMap, f32)]) -> Self {
+        let Some(((first, _), rest)) = fields.split_first() else {
+            return Self::default();
+        };
+        let dist = first
+            .dist
+            .keys()
+            .filter(|c| rest.iter().all(|(f, _)| f.dist.contains_key(c)))
+            .map(|&c| {
+                let sum: f32 = fields.iter().map(|(f, w)| f.dist[&c] as f32 * w).sum();
+                (c, sum.round() as i32)
+            })
+            .collect();
+        Self { dist }
+    }
+
+    /// Flee map: scale the field by `-coefficient` and flood again, so walking downhill moves
+    /// away from the goals while still routing around dead ends. A coefficient a little above 1
+    /// (around 1.2) makes cornered movers break past the threat rather than hug a wall.
+    pub fn inverted(&self, coefficient: f32, policy: &dyn PathPolicy, max_cost: i32) -> Self {
+        let seeds = self
+            .dist
+            .iter()
+            .map(|(&c, &d)| (c, -(d as f32 * coefficient).round() as i32));
+        Self::from_seeds(seeds, policy, max_cost)
+    }
+
+    /// Cheapest downhill neighbour of `from`, counting the step itself, or `None` at a local minimum.
+    /// `from` itself may be outside the field, e.g. when the mover's own cell was not passable.
+    pub fn next_step(&self, from: GridCoord, policy: &dyn PathPolicy) -> Option<GridCoord> {
+        let here = self.get(from).unwrap_or(i32::MAX);
+        let mut best: Option<(i32, GridCoord)> = None;
+        for n in policy.neighbours(from) {
+            let Some(d) = self.get(n) else {
+                continue;
+            };
+            if d >= here {
+                continue;
+            }
+            let total = d.saturating_add(policy.cost(from, n) as i32);
+            if best.is_none_or(|(b, _)| total < b) {
+                best = Some((total, n));
+            }
+        }
+        best.map(|(_, n)| n)
+    }
+
+    /// Every cell walked by following `next_step` from `from` until it stops, starting with `from`.
+    pub fn path_from(&self, from: GridCoord, policy: &dyn PathPolicy) -> Vec<GridCoord> {
+        let mut path = vec![from];
+        let mut at = from;
+        // values strictly decrease along the walk, so it always ends
+        while let Some(next) = self.next_step(at, policy) {
+            path.push(next);
+            at = next;
+        }
+        path
+    }
+}
@@ -0,0 +1,80 @@
+use crate::grid::{Dir, GridCoord};
+use crate::pathfinding::dijkstra::DijkstraMap;
+use crate::pathfinding::policy::{PathPolicy, PolicyExt};
+use crate::pathfinding::stats::SearchStats;
+use std::collections::{HashMap, HashSet};
+
+/// Flow field: the `Dir` to step in from every cell to head towards the nearest target.
+/// Built once and read by any number of movers, so a horde costs one flood instead of one
+/// search per actor.
+#[derive(Debug, Clone, Default)]
+pub struct FlowField {
+    distances: DijkstraMap,
+    dirs: HashMap<GridCoord, Dir>,
+}
+
+impl FlowField {
+    /// Field towards `targets`. Cells costing more than `max_cost` get no direction.
+    pub fn build(
+        targets: impl IntoIterator<Item = GridCoord>,
+        policy: &dyn PathPolicy,
+        max_cost: i32,
+    ) -> Self {
+        Self::from_distances(DijkstraMap::build(targets, policy, max_cost), policy)
+    }
+
+    /// `build` that also fills `stats` with what the flood behind it did.
+    pub fn build_traced(
+        targets: impl IntoIterator<Item = GridCoord>,
+        policy: &dyn PathPolicy,
+        max_cost: i32,
+        stats: &mut SearchStats,
+    ) -> Self {
+        let distances = DijkstraMap::build_traced(targets, policy, max_cost, stats);
+        Self::from_distances(distances, policy)
+    }
+
+    fn from_distances(distances: DijkstraMap, policy: &dyn PathPolicy) -> Self {
+        // Every cell in the field, plus blocked cells beside it so movers standing on
+        // cells the policy treats as occupied (their own, usually) still get a direction
+        let mut cells: HashSet<GridCoord> = distances.iter().map(|(c, _)| c).collect();
+        let fringe: Vec<GridCoord> = cells
+            .iter()
+            .flat_map(|&c| policy.movement().dirs().iter().map(move |d| d.step(c)))
+            .filter_map(|n| policy.wrap(n))
+            .collect();
+        cells.extend(fringe);
+
+        let dirs = cells
+            .into_iter()
+            .filter_map(|c| {
+                let next = distances.next_step(c, policy)?;
+                let (dx, dy) = policy.delta(c, next);
+                Some((c, Dir::from_offset(dx, dy)?))
+            })
+            .collect();
+        Self { distances, dirs }
+    }
+
+    /// Direction to move from `c`; `None` on a target, or where no target can be reached.
+    pub fn dir_at(&self, c: GridCoord) -> Option<Dir> {
+        self.dirs.get(&c).copied()
+    }
+
+    /// Cost from `c` to the nearest target.
+    pub fn distance(&self, c: GridCoord) -> Option<i32> {
+        self.distances.get(c)
+    }
+
+    /// Every cell walked by following the field from `from`, starting with `from`.
+    pub fn path_from(&self, from: GridCoord, policy: &dyn PathPolicy) -> Vec<GridCoord> {
+        let mut path = vec![from];
+        let mut at = from;
+        // each step lowers the distance, so the walk always ends
+        while let Some(next) = self.dir_at(at).and_then(|d| policy.wrap(d.step(at))) {
+            path.push(next);
+            at = next;
+        }
+        path
+    }
+}
@@ -0,0 +1,542 @@
+use bevy::prelude::*;
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::sync::Arc;
+
+use crate::components::{Footprint, Position};
+use crate::grid::{
+    Dir, GridBounds, GridCoord, MovementMode, OccupancyIndex, StaticLayout, WallChanges,
+};
+use crate::pathfinding::astar::{Node, astar, astar_footprint_traced, step_cost};
+use crate::pathfinding::dijkstra::DijkstraMap;
+use crate::pathfinding::policy::{PathPolicy, PolicyExt};
+use crate::pathfinding::stats::{SearchStats, Trace};
+
+/// Side length of a cluster, in cells.
+pub const HPA_CLUSTER_SIZE: i32 = 10;
+/// Levels at least this wide or tall get a hierarchical graph; smaller ones are cheap to search directly.
+pub const HPA_MIN_SIDE: i32 = 64;
+
+/// Cluster column and row, counted from the level origin.
+pub type ClusterId = (i32, i32);
+
+// a run of crossings this long gets an entrance at each end instead of one in the middle
+const LONG_ENTRANCE: usize = 6;
+
+/// HPA* abstraction of the static layout: the level is cut into square clusters, entrances are
+/// picked along the borders between them, and the in-cluster cost between every pair of entrances
+/// is precomputed. Searches run over this small graph and are then refined into cells.
+///
+/// The graph only knows about walls and doors; `refine` takes the live policy so actors are avoided.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct HpaGraph {
+    bounds: Option<GridBounds>,
+    movement: MovementMode,
+    cluster_size: i32,
+    walls: Arc<HashSet<GridCoord>>,
+    // crossings chosen as entrances, keyed by the ordered pair of clusters; cells listed in key order
+    entrances: HashMap<(ClusterId, ClusterId), Vec<(GridCoord, GridCoord)>>,
+    // in-cluster cost between entrance cells
+    intra: HashMap<ClusterId, Vec<(GridCoord, GridCoord, u32)>>,
+    edges: HashMap<GridCoord, Vec<(GridCoord, u32)>>,
+}
+
+impl HpaGraph {
+    pub fn build(
+        bounds: GridBounds,
+        movement: MovementMode,
+        cluster_size: i32,
+        walls: HashSet<GridCoord>,
+    ) -> Self {
+        let mut graph = Self {
+            bounds: Some(bounds),
+            movement,
+            cluster_size: cluster_size.max(2),
+            walls: Arc::new(walls),
+            ..Default::default()
+        };
+        let all: HashSet<ClusterId> = graph.clusters().collect();
+        graph.rebuild_clusters(&all);
+        graph
+    }
+
+    pub fn is_built(&self) -> bool {
+        self.bounds.is_some()
+    }
+
+    pub fn cluster_size(&self) -> i32 {
+        self.cluster_size
+    }
+
+    /// Number of abstract nodes (entrance cells).
+    pub fn node_count(&self) -> usize {
+        self.edges.len()
+    }
+
+    /// Apply a new set of walls, recomputing only the clusters around cells that changed.
+    pub fn update_walls(&mut self, walls: HashSet<GridCoord>) {
+        let dirty: HashSet<ClusterId> = walls
+            .symmetric_difference(&self.walls)
+            .filter(|c| self.in_bounds(**c))
+            .map(|c| self.cluster_of(*c))
+            .collect();
+        self.walls = Arc::new(walls);
+        if !dirty.is_empty() {
+            self.rebuild_clusters(&dirty);
+        }
+    }
+
+    /// Cells of the abstract route from `start` to `goal`: start, entrance cells, goal.
+    pub fn abstract_path(&self, start: GridCoord, goal: GridCoord) -> Option<Vec<GridCoord>> {
+        self.abstract_search(start, goal, &mut Trace::new(None))
+    }
+
+    fn abstract_search(
+        &self,
+        start: GridCoord,
+        goal: GridCoord,
+        trace: &mut Trace,
+    ) -> Option<Vec<GridCoord>> {
+        let bounds = self.bounds?;
+        let (start, goal) = (bounds.normalize(start), bounds.normalize(goal));
+        if !self.in_bounds(start) || !self.in_bounds(goal) {
+            return None;
+        }
+        if start == goal {
+            return Some(vec![start]);
+        }
+
+        // Temporarily connect start and goal to the entrances of their clusters
+        let (start_cluster, goal_cluster) = (self.cluster_of(start), self.cluster_of(goal));
+        let from_start = self.local_costs(start, start_cluster);
+        let to_goal: HashMap<GridCoord, u32> =
+            self.local_costs(goal, goal_cluster).into_iter().collect();
+        let direct = (start_cluster == goal_cluster)
+            .then(|| DijkstraMap::build([start], &self.policy(start_cluster), i32::MAX).get(goal))
+            .flatten();
+
+        let mut open = BinaryHeap::new();
+        let mut came: HashMap<GridCoord, GridCoord> = HashMap::new();
+        let mut g: HashMap<GridCoord, u32> = HashMap::new();
+        g.insert(start, 0);
+        // ties on f go to the entrance nearer the goal, as in A*
+        let h = self.heuristic(start, goal);
+        open.push(Node {
+            pos: start,
+            f: h,
+            g: 0,
+            h,
+        });
+
+        while let Some(Node { pos, g: gscore, .. }) = open.pop() {
+            if pos == goal {
+                let mut path = vec![pos];
+                let mut cur = pos;
+                while let Some(&p) = came.get(&cur) {
+                    path.push(p);
+                    cur = p;
+                }
+                path.reverse();
+                return Some(path);
+            }
+            trace.expand(pos);
+
+            let mut next: Vec<(GridCoord, u32)> = self.edges.get(&pos).cloned().unwrap_or_default();
+            if pos == start {
+                next.extend(from_start.iter().copied());
+                next.extend(direct.map(|d| (goal, d as u32)));
+            }
+            if let Some(&cost) = to_goal.get(&pos) {
+                next.push((goal, cost));
+            }
+
+            for (n, cost) in next {
+                let tentative = gscore + cost;
+                if tentative < *g.get(&n).unwrap_or(&u32::MAX) {
+                    came.insert(n, pos);
+                    g.insert(n, tentative);
+                    let h = self.heuristic(n, goal);
+                    open.push(Node {
+                        pos: n,
+                        f: tentative + h,
+                        g: tentative,
+                        h,
+                    });
+                }
+            }
+            trace.open_len(open.len());
+        }
+        None
+    }
+
+    /// Expand an abstract route into every cell walked, searching each leg inside its cluster
+    /// under `policy`. `None` when live obstacles block a leg.
+    pub fn refine(&self, route: &[GridCoord], policy: &dyn PathPolicy) -> Option<Vec<GridCoord>> {
+        self.refine_legs(route, policy, &mut Trace::new(None))
+    }
+
+    fn refine_legs(
+        &self,
+        route: &[GridCoord],
+        policy: &dyn PathPolicy,
+        trace: &mut Trace,
+    ) -> Option<Vec<GridCoord>> {
+        let mut path = vec![*route.first()?];
+        for leg in route.windows(2) {
+            let (from, to) = (leg[0], leg[1]);
+            let cluster = self.cluster_of(from);
+            if cluster != self.cluster_of(to) {
+                // border crossing: a single step
+                if !policy.passable(to) {
+                    return None;
+                }
+                path.push(to);
+                continue;
+            }
+            let local = ClusterPolicy {
+                graph: self,
+                cluster,
+                inner: policy,
+            };
+            let mut part = trace.part();
+            let leg = match part.as_mut() {
+                Some(stats) => {
+                    astar_footprint_traced(from, to, Footprint::SINGLE, &local, stats).complete()
+                }
+                None => astar(from, to, &local),
+            };
+            trace.add(part);
+            path.extend(leg?.into_iter().skip(1));
+        }
+        Some(path)
+    }
+
+    /// Hierarchical search refined into cells; `None` when the graph is not built or no route exists.
+    pub fn find_path(
+        &self,
+        start: GridCoord,
+        goal: GridCoord,
+        policy: &dyn PathPolicy,
+    ) -> Option<Vec<GridCoord>> {
+        self.search(start, goal, policy, None)
+    }
+
+    /// `find_path` that also fills `stats` with what the search did: the nodes of the abstract
+    /// search, then those of every leg refined inside a cluster.
+    pub fn find_path_traced(
+        &self,
+        start: GridCoord,
+        goal: GridCoord,
+        policy: &dyn PathPolicy,
+        stats: &mut SearchStats,
+    ) -> Option<Vec<GridCoord>> {
+        self.search(start, goal, policy, Some(stats))
+    }
+
+    fn search(
+        &self,
+        start: GridCoord,
+        goal: GridCoord,
+        policy: &dyn PathPolicy,
+        stats: Option<&mut SearchStats>,
+    ) -> Option<Vec<GridCoord>> {
+        if !self.is_built() {
+            return None;
+        }
+        let mut trace = Trace::new(stats);
+        let path = self
+            .abstract_search(start, goal, &mut trace)
+            .and_then(|route| self.refine_legs(&route, policy, &mut trace));
+        trace.finish(path.as_ref().map_or(0, Vec::len));
+        path
+    }
+
+    fn in_bounds(&self, c: GridCoord) -> bool {
+        self.bounds.is_some_and(|b| b.contains(c))
+    }
+
+    fn cluster_of(&self, c: GridCoord) -> ClusterId {
+        let origin = self.bounds.map_or(GridCoord::ZERO, |b| b.origin);
+        (
+            (c.x - origin.x).div_euclid(self.cluster_size),
+            (c.y - origin.y).div_euclid(self.cluster_size),
+        )
+    }
+
+    fn cluster_counts(&self) -> (i32, i32) {
+        let b = self.bounds.unwrap_or(GridBounds::new(0, 0));
+        let size = self.cluster_size;
+        ((b.width + size - 1) / size, (b.height + size - 1) / size)
+    }
+
+    fn clusters(&self) -> impl Iterator<Item = ClusterId> {
+        let (cols, rows) = self.cluster_counts();
+        (0..rows).flat_map(move |y| (0..cols).map(move |x| (x, y)))
+    }
+
+    fn cells(&self, cluster: ClusterId) -> impl Iterator<Item = GridCoord> {
+        let b = self.bounds.unwrap_or(GridBounds::new(0, 0));
+        let size = self.cluster_size;
+        let (x0, y0) = (cluster.0 * size, cluster.1 * size);
+        let (x1, y1) = ((x0 + size).min(b.width), (y0 + size).min(b.height));
+        (y0..y1).flat_map(move |y| {
+            (x0..x1).map(move |x| GridCoord::new(b.origin.x + x, b.origin.y + y))
+        })
+    }
+
+    // clusters touching `cluster`, including diagonally and across a wrapping edge
+    fn neighbour_clusters(&self, cluster: ClusterId) -> Vec<ClusterId> {
+        let (cols, rows) = self.cluster_counts();
+        let wrap = self.bounds.is_some_and(|b| b.wrap);
+        let mut out = Vec::new();
+        for dy in -1..=1 {
+            for dx in -1..=1 {
+                let (mut x, mut y) = (cluster.0 + dx, cluster.1 + dy);
+                if wrap {
+                    x = x.rem_euclid(cols);
+                    y = y.rem_euclid(rows);
+                } else if x < 0 || y < 0 || x >= cols || y >= rows {
+                    continue;
+                }
+                if (x, y) != cluster && !out.contains(&(x, y)) {
+                    out.push((x, y));
+                }
+            }
+        }
+        out
+    }
+
+    // static policy over the walls, confined to one cluster
+    fn policy(&self, cluster: ClusterId) -> ClusterPolicy<'_> {
+        ClusterPolicy {
+            graph: self,
+            cluster,
+            inner: self,
+        }
+    }
+
+    // in-cluster cost from `from` to each entrance cell of `cluster`
+    fn local_costs(&self, from: GridCoord, cluster: ClusterId) -> Vec<(GridCoord, u32)> {
+        let field = DijkstraMap::build([from], &self.policy(cluster), i32::MAX);
+        self.entrance_cells(cluster)
+            .into_iter()
+            .filter_map(|e| field.get(e).map(|d| (e, d as u32)))
+            .collect()
+    }
+
+    fn entrance_cells(&self, cluster: ClusterId) -> Vec<GridCoord> {
+        let mut cells: Vec<GridCoord> = self
+            .entrances
+            .iter()
+            .flat_map(|(&(a, b), pairs)| {
+                pairs.iter().filter_map(move |&(ca, cb)| {
+                    if a == cluster {
+                        Some(ca)
+                    } else if b == cluster {
+                        Some(cb)
+                    } else {
+                        None
+                    }
+                })
+            })
+            .collect();
+        cells.sort_by_key(|c| (c.y, c.x));
+        cells.dedup();
+        cells
+    }
+
+    fn rebuild_clusters(&mut self, dirty: &HashSet<ClusterId>) {
+        // Entrances depend on both sides of a border, and diagonal crossings on the corner
+        // clusters too, so redo every border around the dirty clusters
+        let mut affected: HashSet<ClusterId> = dirty.clone();
+        for &k in dirty {
+            affected.extend(self.neighbour_clusters(k));
+        }
+        let mut affected: Vec<ClusterId> = affected.into_iter().collect();
+        affected.sort_unstable();
+
+        for &a in &affected {
+            for b in self.neighbour_clusters(a) {
+                if a < b && affected.contains(&b) {
+                    let entrances = self.border_entrances(a, b);
+                    self.entrances.insert((a, b), entrances);
+                }
+            }
+        }
+        for &k in &affected {
+            let intra = self.intra_costs(k);
+            self.intra.insert(k, intra);
+        }
+        self.entrances.retain(|_, pairs| !pairs.is_empty());
+        self.rebuild_edges();
+    }
+
+    // Crossings from cluster `a` into cluster `b`, thinned out to entrances
+    fn border_entrances(&self, a: ClusterId, b: ClusterId) -> Vec<(GridCoord, GridCoord)> {
+        let Some(bounds) = self.bounds else {
+            return Vec::new();
+        };
+        // group straight crossings by direction, so the two borders of a wrapped pair stay apart
+        let mut straight: HashMap<Dir, Vec<(GridCoord, GridCoord)>> = HashMap::new();
+        let mut entrances = Vec::new();
+        for c in self.cells(a).filter(|&c| self.passable(c)) {
+            for &dir in self.movement.dirs() {
+                let n = dir.step_in(c, &bounds);
+                if self.cluster_of(n) != b || !self.passable(n) {
+                    continue;
+                }
+                match dir.components() {
+                    None => straight.entry(dir).or_default().push((c, n)),
+                    // a diagonal is only needed when it squeezes between two blocked cells
+                    Some((h, v)) => {
+                        if self.movement.cuts_corners()
+                            && !self.passable(h.step_in(c, &bounds))
+                            && !self.passable(v.step_in(c, &bounds))
+                        {
+                            entrances.push((c, n));
+                        }
+                    }
+                }
+            }
+        }
+
+        for dir in Dir::CARDINAL {
+            let Some(mut crossings) = straight.remove(&dir) else {
+                continue;
+            };
+            crossings.sort_by_key(|(c, _)| (c.y, c.x));
+            let mut runs: Vec<Vec<(GridCoord, GridCoord)>> = Vec::new();
+            for crossing in crossings {
+                let continues = runs.last().and_then(|run| run.last()).is_some_and(|prev| {
+                    let (dx, dy) = bounds.delta(prev.0, crossing.0);
+                    dx.abs() + dy.abs() == 1
+                });
+                match runs.last_mut() {
+                    Some(run) if continues => run.push(crossing),
+                    _ => runs.push(vec![crossing]),
+                }
+            }
+            for run in runs {
+                if run.len() >= LONG_ENTRANCE {
+                    entrances.push(run[0]);
+                    entrances.push(run[run.len() - 1]);
+                } else {
+                    entrances.push(run[run.len() / 2]);
+                }
+            }
+        }
+        entrances
+    }
+
+    fn intra_costs(&self, cluster: ClusterId) -> Vec<(GridCoord, GridCoord, u32)> {
+        let cells = self.entrance_cells(cluster);
+        let policy = self.policy(cluster);
+        let mut costs = Vec::new();
+        for &from in &cells {
+            let field = DijkstraMap::build([from], &policy, i32::MAX);
+            for &to in &cells {
+                if let Some(d) = field.get(to).filter(|_| to != from) {
+                    costs.push((from, to, d as u32));
+                }
+            }
+        }
+        costs
+    }
+
+    fn rebuild_edges(&mut self) {
+        let mut edges: HashMap<GridCoord, Vec<(GridCoord, u32)>> = HashMap::new();
+        for pairs in self.entrances.values() {
+            for &(a, b) in pairs {
+                edges.entry(a).or_default().push((b, step_cost(a, b)));
+                edges.entry(b).or_default().push((a, step_cost(b, a)));
+            }
+        }
+        for costs in self.intra.values() {
+            for &(from, to, cost) in costs {
+                edges.entry(from).or_default().push((to, cost));
+            }
+        }
+        // map iteration order is arbitrary; sort so searches tie-break the same way every run
+        for list in edges.values_mut() {
+            list.sort_by_key(|&(c, cost)| (cost, c.y, c.x));
+            list.dedup();
+        }
+        self.edges = edges;
+    }
+}
+
+// ECS system: keep the hierarchical graph in step with walls and doors on large levels
+pub fn update_hpa_graph(
+    mut graph: ResMut<HpaGraph>,
+    occ: Res<OccupancyIndex>,
+    movement: Res<MovementMode>,
+    mut changes: WallChanges,
+    q_walls: Query<&Position, StaticLayout>,
+    mut built_for: Local<Option<(Option<GridBounds>, MovementMode)>>,
+) {
+    let changed = changes.any();
+    let key = (occ.bounds(), *movement);
+    if !changed && *built_for == Some(key) {
+        return;
+    }
+
+    let large = occ
+        .bounds()
+        .filter(|b| b.width.max(b.height) >= HPA_MIN_SIDE);
+    let Some(bounds) = large else {
+        *graph = HpaGraph::default();
+        *built_for = Some(key);
+        return;
+    };
+
+    let walls: HashSet<GridCoord> = q_walls.iter().map(|p| bounds.normalize(p.0)).collect();
+    if *built_for == Some(key) && graph.is_built() {
+        graph.update_walls(walls);
+    } else {
+        *graph = HpaGraph::build(bounds, *movement, HPA_CLUSTER_SIZE, walls);
+    }
+    *built_for = Some(key);
+}
+
+// The graph itself is the static policy: walls block, everything else in bounds is open
+impl PathPolicy for HpaGraph {
+    fn passable(&self, c: GridCoord) -> bool {
+        self.in_bounds(c) && !self.walls.contains(&c)
+    }
+
+    fn bounds(&self) -> Option<GridBounds> {
+        self.bounds
+    }
+
+    fn movement(&self) -> MovementMode {
+        self.movement
+    }
+}
+
+// `inner` confined to the cells of one cluster
+struct ClusterPolicy<'a> {
+    graph: &'a HpaGraph,
+    cluster: ClusterId,
+    inner: &'a dyn PathPolicy,
+}
+
+impl PathPolicy for ClusterPolicy<'_> {
+    fn passable(&self, c: GridCoord) -> bool {
+        self.graph.cluster_of(c) == self.cluster && self.inner.passable(c)
+    }
+
+    fn cost(&self, from: GridCoord, to: GridCoord) -> u32 {
+        self.inner.cost(from, to)
+    }
+
+    fn bounds(&self) -> Option<GridBounds> {
+        self.inner.bounds()
+    }
+
+    fn movement(&self) -> MovementMode {
+        self.inner.movement()
+    }
+
+    fn neighbours(&self, c: GridCoord) -> Vec<GridCoord> {
+        self.inner.neighbours(c)
+    }
+}
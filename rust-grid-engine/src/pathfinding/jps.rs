@@ -0,0 +1,292 @@
+use crate::grid::{Dir, GridCoord, MovementMode};
+use crate::pathfinding::astar::{Node, PathResult, astar_search};
+use crate::pathfinding::policy::{PathPolicy, PolicyExt};
+use crate::pathfinding::stats::{SearchStats, Trace};
+use std::collections::{BinaryHeap, HashMap};
+
+/// Jump Point Search: A* that skips over runs of open cells and only stops on cells where the
+/// optimal route might turn. Returns the same path lengths as `astar` with far fewer expansions
+/// on open maps. Assumes uniform step costs (`step_cost`); the returned path is every cell
+/// walked, like `astar`.
+///
+/// Levels without bounds fall back to plain A*, since a jump across open space would never end.
+pub fn jps(start: GridCoord, goal: GridCoord, policy: &dyn PathPolicy) -> Option<Vec<GridCoord>> {
+    jps_partial(start, goal, policy).complete()
+}
+
+/// Like `jps`, but when the goal is unreachable or `max_expansions` runs out it returns the
+/// path towards the closest jump point explored instead of nothing, as `astar_partial` does.
+/// The budget counts jump points, not every cell a run passes over.
+pub fn jps_partial(start: GridCoord, goal: GridCoord, policy: &dyn PathPolicy) -> PathResult {
+    jump_search(start, goal, policy, None)
+}
+
+/// `jps_partial` that also fills `stats` with what the search did; `explored` lists the jump
+/// points expanded.
+pub fn jps_traced(
+    start: GridCoord,
+    goal: GridCoord,
+    policy: &dyn PathPolicy,
+    stats: &mut SearchStats,
+) -> PathResult {
+    jump_search(start, goal, policy, Some(stats))
+}
+
+pub(crate) fn jump_search(
+    start: GridCoord,
+    goal: GridCoord,
+    policy: &dyn PathPolicy,
+    stats: Option<&mut SearchStats>,
+) -> PathResult {
+    let Some(bounds) = policy.bounds() else {
+        return astar_search(start, goal, policy, stats);
+    };
+    let search = Jps {
+        policy,
+        goal,
+        // on wrapping maps a straight or diagonal run repeats after at most width * height steps
+        max_run: bounds.width.max(1) as usize * bounds.height.max(1) as usize,
+    };
+    search.run(start, Trace::new(stats))
+}
+
+struct Jps<'a> {
+    policy: &'a dyn PathPolicy,
+    goal: GridCoord,
+    max_run: usize,
+}
+
+impl Jps<'_> {
+    // Same open list order, budget and partial result as `astar`, over jump points
+    fn run(&self, start: GridCoord, mut trace: Trace) -> PathResult {
+        let mut open = BinaryHeap::new();
+        // jump point -> (previous jump point, direction of the run between them)
+        let mut came: HashMap<GridCoord, (GridCoord, Dir)> = HashMap::new();
+        let mut g: HashMap<GridCoord, u32> = HashMap::new();
+        let mut expanded = 0;
+
+        let h = self.policy.heuristic(start, self.goal);
+        g.insert(start, 0);
+        open.push(Node {
+            pos: start,
+            f: h,
+            g: 0,
+            h,
+        });
+        // closest jump point to the goal so far, for partial results: lowest h, then cheapest
+        let mut best = (h, 0, start);
+
+        while let Some(Node {
+            pos,
+            g: gscore,
+            h: hscore,
+            ..
+        }) = open.pop()
+        {
+            if gscore > g[&pos] {
+                // stale entry, a cheaper route to `pos` was already expanded
+                continue;
+            }
+            if pos == self.goal {
+                let path = self.reconstruct(pos, &came);
+                trace.finish(path.len());
+                return PathResult::Complete(path);
+            }
+            if self
+                .policy
+                .max_expansions()
+                .is_some_and(|max| expanded >= max)
+            {
+                break;
+            }
+            expanded += 1;
+            trace.expand(pos);
+            if (hscore, gscore) < (best.0, best.1) {
+                best = (hscore, gscore, pos);
+            }
+
+            let incoming = came.get(&pos).map(|&(_, dir)| dir);
+            for dir in self.successors(pos, incoming) {
+                let Some((jump_point, cost)) = self.jump(pos, dir) else {
+                    continue;
+                };
+                let tentative = gscore + cost;
+                if tentative < *g.get(&jump_point).unwrap_or(&u32::MAX) {
+                    came.insert(jump_point, (pos, dir));
+                    g.insert(jump_point, tentative);
+                    let h = self.policy.heuristic(jump_point, self.goal);
+                    open.push(Node {
+                        pos: jump_point,
+                        f: tentative + h,
+                        g: tentative,
+                        h,
+                    });
+                }
+            }
+            trace.open_len(open.len());
+        }
+        let path = self.reconstruct(best.2, &came);
+        trace.finish(path.len());
+        PathResult::Partial(path)
+    }
+
+    // Expand the jump points back into every cell walked
+    fn reconstruct(
+        &self,
+        goal: GridCoord,
+        came: &HashMap<GridCoord, (GridCoord, Dir)>,
+    ) -> Vec<GridCoord> {
+        let mut runs = Vec::new();
+        let mut cur = goal;
+        while let Some(&(prev, dir)) = came.get(&cur) {
+            runs.push((prev, dir, cur));
+            cur = prev;
+        }
+
+        let mut path = vec![cur];
+        for (from, dir, to) in runs.into_iter().rev() {
+            let mut at = from;
+            while at != to {
+                at = self.step(at, dir).expect("jump runs stay inside the map");
+                path.push(at);
+            }
+        }
+        path
+    }
+
+    fn step(&self, c: GridCoord, dir: Dir) -> Option<GridCoord> {
+        self.policy.wrap(dir.step(c))
+    }
+
+    fn walkable(&self, c: GridCoord, dx: i32, dy: i32) -> bool {
+        self.policy
+            .wrap(GridCoord::new(c.x + dx, c.y + dy))
+            .is_some_and(|n| self.policy.passable(n))
+    }
+
+    fn can_step(&self, c: GridCoord, dir: Dir) -> bool {
+        let (dx, dy) = dir.offset();
+        if !self.walkable(c, dx, dy) {
+            return false;
+        }
+        !dir.is_diagonal()
+            || self.policy.movement().cuts_corners()
+            || (self.walkable(c, dx, 0) && self.walkable(c, 0, dy))
+    }
+
+    // Directions worth searching from a jump point reached by travelling `incoming`
+    fn successors(&self, c: GridCoord, incoming: Option<Dir>) -> Vec<Dir> {
+        let movement = self.policy.movement();
+        let Some(dir) = incoming else {
+            return movement.dirs().to_vec();
+        };
+        let (dx, dy) = dir.offset();
+        let blocked = |ox: i32, oy: i32| !self.walkable(c, ox, oy);
+
+        let offsets: Vec<(i32, i32)> = match (movement, dir.is_diagonal()) {
+            (MovementMode::EightWay, true) => {
+                let mut out = vec![(0, dy), (dx, 0), (dx, dy)];
+                if blocked(-dx, 0) {
+                    out.push((-dx, dy));
+                }
+                if blocked(0, -dy) {
+                    out.push((dx, -dy));
+                }
+                out
+            }
+            (MovementMode::EightWay, false) => {
+                // the two cells beside the run; forced diagonals open up when one is blocked
+                let (sx, sy) = (dy, dx);
+                let mut out = vec![(dx, dy)];
+                if blocked(sx, sy) {
+                    out.push((dx + sx, dy + sy));
+                }
+                if blocked(-sx, -sy) {
+                    out.push((dx - sx, dy - sy));
+                }
+                out
+            }
+            (MovementMode::EightWayNoCornerCut, true) => vec![(0, dy), (dx, 0), (dx, dy)],
+            (MovementMode::EightWayNoCornerCut, false) => {
+                let (sx, sy) = (dy, dx);
+                vec![
+                    (dx, dy),
+                    (dx + sx, dy + sy),
+                    (dx - sx, dy - sy),
+                    (sx, sy),
+                    (-sx, -sy),
+                ]
+            }
+            (MovementMode::FourWay, _) => {
+                let (sx, sy) = (dy, dx);
+                vec![(dx, dy), (sx, sy), (-sx, -sy)]
+            }
+        };
+        offsets
+            .into_iter()
+            .filter_map(|(ox, oy)| Dir::from_offset(ox, oy))
+            .filter(|d| movement.allows(*d))
+            .collect()
+    }
+
+    // Walk from `from` in `dir` until reaching the goal or a jump point; returns it and the cost of the run
+    fn jump(&self, from: GridCoord, dir: Dir) -> Option<(GridCoord, u32)> {
+        let mut cur = from;
+        let mut cost = 0;
+        for _ in 0..self.max_run {
+            if !self.can_step(cur, dir) {
+                return None;
+            }
+            let next = self.step(cur, dir)?;
+            cost += self.policy.cost(cur, next);
+            cur = next;
+            if cur == self.goal || cur == from || self.is_jump_point(cur, dir) {
+                // returning to `from` means the run looped around a wrapping map
+                return (cur != from).then_some((cur, cost));
+            }
+        }
+        None
+    }
+
+    fn is_jump_point(&self, c: GridCoord, dir: Dir) -> bool {
+        let (dx, dy) = dir.offset();
+        let w = |ox: i32, oy: i32| self.walkable(c, ox, oy);
+        let runs_find_something =
+            |a: Dir, b: Dir| self.jump(c, a).is_some() || self.jump(c, b).is_some();
+
+        match (self.policy.movement(), dir.is_diagonal()) {
+            (MovementMode::EightWay, true) => {
+                (w(-dx, dy) && !w(-dx, 0))
+                    || (w(dx, -dy) && !w(0, -dy))
+                    || runs_find_something(
+                        Dir::from_offset(dx, 0).unwrap(),
+                        Dir::from_offset(0, dy).unwrap(),
+                    )
+            }
+            (MovementMode::EightWay, false) => {
+                let (sx, sy) = (dy, dx);
+                (w(dx + sx, dy + sy) && !w(sx, sy)) || (w(dx - sx, dy - sy) && !w(-sx, -sy))
+            }
+            (MovementMode::EightWayNoCornerCut, true) => runs_find_something(
+                Dir::from_offset(dx, 0).unwrap(),
+                Dir::from_offset(0, dy).unwrap(),
+            ),
+            (MovementMode::EightWayNoCornerCut, false) => {
+                // a side opens up that was walled off one step back
+                let (sx, sy) = (dy, dx);
+                (w(sx, sy) && !w(sx - dx, sy - dy)) || (w(-sx, -sy) && !w(-sx - dx, -sy - dy))
+            }
+            (MovementMode::FourWay, false) => {
+                let (sx, sy) = (dy, dx);
+                if dx == 0 {
+                    // vertical runs stop wherever a path could turn off them, so only horizontal
+                    // runs skip ahead and neither has to scan the other
+                    w(sx, sy) || w(-sx, -sy)
+                } else {
+                    (w(sx, sy) && !w(sx - dx, sy - dy)) || (w(-sx, -sy) && !w(-sx - dx, -sy - dy))
+                }
+            }
+            (MovementMode::FourWay, true) => false,
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::grid::{GridBounds, GridCoord, MovementMode};
+use crate::pathfinding::policy::{PathPolicy, PolicyExt};
+use crate::pathfinding::stats::{SearchStats, Trace};
+
+/// Highest key id a `KeySet` can hold.
+pub const MAX_KEY_ID: i32 = 63;
+
+/// Set of key ids `0..=MAX_KEY_ID`, small enough to be part of a search state.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct KeySet(u64);
+
+impl KeySet {
+    pub fn insert(&mut self, id: i32) {
+        if (0..=MAX_KEY_ID).contains(&id) {
+            self.0 |= 1 << id;
+        }
+    }
+
+    pub fn contains(self, id: i32) -> bool {
+        (0..=MAX_KEY_ID).contains(&id) && self.0 & (1 << id) != 0
+    }
+
+    pub fn with(mut self, id: i32) -> Self {
+        self.insert(id);
+        self
+    }
+
+    /// True when a door with this lock opens for the holder; `None` is a door without a lock.
+    pub fn opens(self, lock: Option<i32>) -> bool {
+        lock.is_none_or(|id| self.contains(id))
+    }
+}
+
+/// Doors and keys on a level: the lock on each door cell (`None` for a plain door),
+/// and the id of the key lying on each key cell.
+#[derive(Debug, Clone, Default)]
+pub struct KeyLayout {
+    pub doors: HashMap<GridCoord, Option<i32>>,
+    pub keys: HashMap<GridCoord, i32>,
+}
+
+/// Route found by `keyed_path`, and the keys held once it ends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyedPath {
+    pub path: Vec<GridCoord>,
+    pub keys: KeySet,
+}
+
+// `inner` with every door open for the holder of `held` and shut for everyone else
+struct KeyedPolicy<'a> {
+    inner: &'a dyn PathPolicy,
+    layout: &'a KeyLayout,
+    held: KeySet,
+}
+
+impl PathPolicy for KeyedPolicy<'_> {
+    fn passable(&self, c: GridCoord) -> bool {
+        match self.layout.doors.get(&c) {
+            Some(&lock) => self.held.opens(lock),
+            None => self.inner.passable(c),
+        }
+    }
+
+    fn cost(&self, from: GridCoord, to: GridCoord) -> u32 {
+        self.inner.cost(from, to)
+    }
+
+    fn bounds(&self) -> Option<GridBounds> {
+        self.inner.bounds()
+    }
+
+    fn movement(&self) -> MovementMode {
+        self.inner.movement()
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq)]
+struct Node {
+    pos: GridCoord,
+    keys: KeySet,
+    f: u32,
+    g: u32,
+    h: u32,
+}
+// Min-heap on f, then h, then the lower coordinate and key set, as in `astar`
+impl Ord for Node {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .f
+            .cmp(&self.f)
+            .then_with(|| other.h.cmp(&self.h))
+            .then_with(|| (other.pos.y, other.pos.x).cmp(&(self.pos.y, self.pos.x)))
+            .then_with(|| other.keys.0.cmp(&self.keys.0))
+    }
+}
+impl PartialOrd for Node {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// A* over (cell, keys held) from `start` to `goal`, starting with `held`. Stepping on a key
+/// cell picks the key up; a door is only passable while holding its key, so the route may
+/// detour to fetch keys first. Doors in `layout` override `policy`, which decides every other
+/// cell. Neighbours come from the movement mode, so `from`-dependent rules are not consulted.
+///
+/// `None` when the goal stays out of reach or `max_expansions` runs out.
+pub fn keyed_path(
+    start: GridCoord,
+    goal: GridCoord,
+    held: KeySet,
+    layout: &KeyLayout,
+    policy: &dyn PathPolicy,
+) -> Option<KeyedPath> {
+    keyed_search(start, goal, held, layout, policy, None)
+}
+
+/// `keyed_path` that also fills `stats` with what the search did. Every (cell, keys) state
+/// expanded counts, so a cell can appear in `explored` once per set of keys.
+pub fn keyed_path_traced(
+    start: GridCoord,
+    goal: GridCoord,
+    held: KeySet,
+    layout: &KeyLayout,
+    policy: &dyn PathPolicy,
+    stats: &mut SearchStats,
+) -> Option<KeyedPath> {
+    keyed_search(start, goal, held, layout, policy, Some(stats))
+}
+
+fn keyed_search(
+    start: GridCoord,
+    goal: GridCoord,
+    held: KeySet,
+    layout: &KeyLayout,
+    policy: &dyn PathPolicy,
+    stats: Option<&mut SearchStats>,
+) -> Option<KeyedPath> {
+    let mut trace = Trace::new(stats);
+    let pick_up = |keys: KeySet, c: GridCoord| match layout.keys.get(&c) {
+        Some(&id) => keys.with(id),
+        None => keys,
+    };
+
+    let mut open = BinaryHeap::new();
+    let mut came: HashMap<(GridCoord, KeySet), (GridCoord, KeySet)> = HashMap::new();
+    let mut g: HashMap<(GridCoord, KeySet), u32> = HashMap::new();
+    let mut expanded = 0;
+
+    let keys = pick_up(held, start);
+    let h = policy.heuristic(start, goal);
+    g.insert((start, keys), 0);
+    open.push(Node {
+        pos: start,
+        keys,
+        f: h,
+        g: 0,
+        h,
+    });
+
+    while let Some(Node {
+        pos,
+        keys,
+        g: gscore,
+        ..
+    }) = open.pop()
+    {
+        if gscore > g[&(pos, keys)] {
+            continue;
+        }
+        if pos == goal {
+            let mut path = vec![pos];
+            let mut cur = (pos, keys);
+            while let Some(&prev) = came.get(&cur) {
+                path.push(prev.0);
+                cur = prev;
+            }
+            path.reverse();
+            trace.finish(path.len());
+            return Some(KeyedPath { path, keys });
+        }
+        if policy.max_expansions().is_some_and(|max| expanded >= max) {
+            break;
+        }
+        expanded += 1;
+        trace.expand(pos);
+
+        let here = KeyedPolicy {
+            inner: policy,
+            layout,
+            held: keys,
+        };
+        for n in here.neighbours(pos) {
+            if !here.passable(n) {
+                continue;
+            }
+            let state = (n, pick_up(keys, n));
+            let tentative = gscore + policy.cost(pos, n);
+            if tentative < *g.get(&state).unwrap_or(&u32::MAX) {
+                came.insert(state, (pos, keys));
+                g.insert(state, tentative);
+                let h = policy.heuristic(n, goal);
+                open.push(Node {
+                    pos: n,
+                    keys: state.1,
+                    f: tentative + h,
+                    g: tentative,
+                    h,
+                });
+            }
+        }
+        trace.open_len(open.len());
+    }
+    trace.finish(0);
+    None
+}
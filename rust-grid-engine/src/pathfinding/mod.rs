@@ -1,2 +1,18 @@
 pub mod astar;
+pub mod cooperative;
+pub mod dijkstra;
+pub mod flow;
+pub mod hpa;
+pub mod jps;
+pub mod keys;
+pub mod policy;
+pub mod stats;
 pub use astar::*;
+pub use cooperative::*;
+pub use dijkstra::*;
+pub use flow::*;
+pub use hpa::*;
+pub use jps::*;
+pub use keys::*;
+pub use policy::{PathPolicy, RulesPolicy};
+pub use stats::*;
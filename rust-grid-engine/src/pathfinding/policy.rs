@@ -0,0 +1,244 @@
+use crate::components::Footprint;
+use crate::engine::rules::{MoveCheck, Rules, check_step};
+use crate::grid::{
+    GridBounds, GridCoord, MovementMode, OccupancyIndex, neighbours_4, neighbours_8,
+};
+use crate::pathfinding::astar::{DIAGONAL_COST, ORTHOGONAL_COST, SearchAlgorithm, step_cost};
+use bevy::prelude::Entity;
+
+/// What a search may walk on and what each step costs. Every search in `pathfinding` takes
+/// one; `RulesPolicy` is the one actors use, so paths obey the same rules as their moves.
+pub trait PathPolicy {
+    /// True when the mover may stand on `c`.
+    fn passable(&self, c: GridCoord) -> bool;
+
+    /// Step cost, in the same units as `ORTHOGONAL_COST` so the heuristic stays admissible.
+    fn cost(&self, from: GridCoord, to: GridCoord) -> u32 {
+        step_cost(from, to)
+    }
+
+    /// Level extents; neighbours outside are skipped, or wrapped on toroidal maps.
+    fn bounds(&self) -> Option<GridBounds>;
+
+    fn movement(&self) -> MovementMode;
+
+    /// Which search `find_path` runs.
+    fn algorithm(&self) -> SearchAlgorithm {
+        SearchAlgorithm::AStar
+    }
+
+    /// Stop after expanding this many nodes; `None` searches until the goal or the whole reachable map.
+    fn max_expansions(&self) -> Option<usize> {
+        None
+    }
+
+    /// Cells one step away from `c`. By default every cell the movement mode reaches, minus
+    /// diagonals past a blocked corner when the mode forbids cutting them. Searches still
+    /// check `passable` on each.
+    fn neighbours(&self, c: GridCoord) -> Vec<GridCoord> {
+        let candidates: Vec<GridCoord> = match self.movement() {
+            MovementMode::FourWay => neighbours_4(c).to_vec(),
+            MovementMode::EightWay | MovementMode::EightWayNoCornerCut => neighbours_8(c).to_vec(),
+        };
+        candidates
+            .into_iter()
+            .filter(|n| self.movement().cuts_corners() || !self.cuts_corner(c, *n))
+            .filter_map(|n| self.wrap(n))
+            .collect()
+    }
+}
+
+/// Grid geometry shared by every policy, derived from its bounds and movement mode.
+pub(crate) trait PolicyExt: PathPolicy {
+    fn wrap(&self, c: GridCoord) -> Option<GridCoord> {
+        match self.bounds() {
+            Some(b) => {
+                let c = b.normalize(c);
+                b.contains(c).then_some(c)
+            }
+            None => Some(c),
+        }
+    }
+
+    // every cell one step of the movement mode away from `c`, whatever the rules say
+    fn adjacent(&self, c: GridCoord) -> Vec<GridCoord> {
+        let cells: Vec<GridCoord> = match self.movement() {
+            MovementMode::FourWay => neighbours_4(c).to_vec(),
+            MovementMode::EightWay | MovementMode::EightWayNoCornerCut => neighbours_8(c).to_vec(),
+        };
+        cells.into_iter().filter_map(|n| self.wrap(n)).collect()
+    }
+
+    // true when a diagonal step squeezes past a blocked orthogonal cell
+    fn cuts_corner(&self, from: GridCoord, to: GridCoord) -> bool {
+        if from.x == to.x || from.y == to.y {
+            return false;
+        }
+        [GridCoord::new(to.x, from.y), GridCoord::new(from.x, to.y)]
+            .into_iter()
+            .any(|side| self.wrap(side).is_none_or(|side| !self.passable(side)))
+    }
+
+    fn delta(&self, a: GridCoord, b: GridCoord) -> (i32, i32) {
+        match self.bounds() {
+            Some(bounds) => bounds.delta(a, b),
+            None => (b.x - a.x, b.y - a.y),
+        }
+    }
+
+    //the estimated cost from a to b: Manhattan for 4-way, octile for 8-way movement
+    fn heuristic(&self, a: GridCoord, b: GridCoord) -> u32 {
+        let (dx, dy) = self.delta(a, b);
+        self.distance(dx.unsigned_abs(), dy.unsigned_abs())
+    }
+
+    // estimated cost until a footprint anchored at `anchor` covers `goal`
+    fn footprint_heuristic(&self, anchor: GridCoord, goal: GridCoord, fp: Footprint) -> u32 {
+        let (dx, dy) = self.delta(anchor, goal);
+        let gap = |d: i32, size: i32| {
+            if d < 0 {
+                d.unsigned_abs()
+            } else {
+                (d - (size.max(1) - 1)).max(0) as u32
+            }
+        };
+        self.distance(gap(dx, fp.width), gap(dy, fp.height))
+    }
+
+    fn distance(&self, dx: u32, dy: u32) -> u32 {
+        match self.movement() {
+            MovementMode::FourWay => ORTHOGONAL_COST * (dx + dy),
+            MovementMode::EightWay | MovementMode::EightWayNoCornerCut => {
+                ORTHOGONAL_COST * dx.max(dy) + (DIAGONAL_COST - ORTHOGONAL_COST) * dx.min(dy)
+            }
+        }
+    }
+}
+
+impl<P: PathPolicy + ?Sized> PolicyExt for P {}
+
+/// Policy over anchor cells: an anchor is passable when every cell the footprint covers is.
+pub(crate) struct FootprintPolicy<'a> {
+    pub inner: &'a dyn PathPolicy,
+    pub footprint: Footprint,
+}
+
+impl PathPolicy for FootprintPolicy<'_> {
+    fn passable(&self, anchor: GridCoord) -> bool {
+        self.footprint.cells(anchor).all(|cell| {
+            self.inner
+                .wrap(cell)
+                .is_some_and(|cell| self.inner.passable(cell))
+        })
+    }
+
+    fn cost(&self, from: GridCoord, to: GridCoord) -> u32 {
+        self.inner.cost(from, to)
+    }
+
+    fn bounds(&self) -> Option<GridBounds> {
+        self.inner.bounds()
+    }
+
+    fn movement(&self) -> MovementMode {
+        self.inner.movement()
+    }
+
+    fn algorithm(&self) -> SearchAlgorithm {
+        self.inner.algorithm()
+    }
+
+    fn max_expansions(&self) -> Option<usize> {
+        self.inner.max_expansions()
+    }
+}
+
+/// Policy for `mover` under the active `Rules`: a cell is passable when `Rules::can_enter`
+/// allows it and a step is a neighbour when `check_step` allows it, so every path found is
+/// one `validate_moves` accepts step by step.
+///
+/// `passable` has no step to judge, so it asks `can_enter` with `from == to`. Footprint
+/// searches combine it per covered cell, which matches `check_step` for rules that ignore `from`.
+pub struct RulesPolicy<'a> {
+    rules: &'a dyn Rules,
+    occ: &'a OccupancyIndex,
+    movement: MovementMode,
+    mover: Entity,
+    algorithm: SearchAlgorithm,
+    max_expansions: Option<usize>,
+}
+
+impl<'a> RulesPolicy<'a> {
+    /// Policy for `mover` on the current occupancy. Use `Entity::PLACEHOLDER` for a policy
+    /// shared by several movers: nobody's own cell is then treated as free.
+    pub fn new(
+        rules: &'a dyn Rules,
+        occ: &'a OccupancyIndex,
+        movement: MovementMode,
+        mover: Entity,
+    ) -> Self {
+        Self {
+            rules,
+            occ,
+            movement,
+            mover,
+            algorithm: SearchAlgorithm::AStar,
+            max_expansions: None,
+        }
+    }
+
+    pub fn with_algorithm(mut self, algorithm: SearchAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    pub fn with_max_expansions(mut self, max_expansions: Option<usize>) -> Self {
+        self.max_expansions = max_expansions;
+        self
+    }
+}
+
+impl PathPolicy for RulesPolicy<'_> {
+    fn passable(&self, c: GridCoord) -> bool {
+        matches!(
+            self.rules.can_enter(self.occ, self.mover, c, c),
+            MoveCheck::Allow
+        )
+    }
+
+    fn bounds(&self) -> Option<GridBounds> {
+        self.occ.bounds()
+    }
+
+    fn movement(&self) -> MovementMode {
+        self.movement
+    }
+
+    fn algorithm(&self) -> SearchAlgorithm {
+        self.algorithm
+    }
+
+    fn max_expansions(&self) -> Option<usize> {
+        self.max_expansions
+    }
+
+    fn neighbours(&self, c: GridCoord) -> Vec<GridCoord> {
+        self.movement
+            .dirs()
+            .iter()
+            .filter(|&&dir| {
+                let check = check_step(
+                    self.rules,
+                    self.occ,
+                    self.movement,
+                    self.mover,
+                    Footprint::SINGLE,
+                    c,
+                    dir,
+                );
+                matches!(check, MoveCheck::Allow)
+            })
+            .map(|&dir| self.occ.step(c, dir))
+            .collect()
+    }
+}
@@ -0,0 +1,177 @@
+use bevy::prelude::*;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::grid::GridCoord;
+
+/// What one search did: nodes expanded, the largest the open list grew, the length of the
+/// returned path (in cells, start included) and how long it took. `explored` lists every
+/// expanded cell in expansion order.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchStats {
+    pub expanded: usize,
+    pub open_peak: usize,
+    pub path_len: usize,
+    pub duration: Duration,
+    pub explored: Vec<GridCoord>,
+}
+
+impl SearchStats {
+    /// Stats for a plan that reused an earlier `path` instead of searching.
+    pub fn reused(path: &[GridCoord]) -> Self {
+        Self {
+            path_len: path.len(),
+            ..Self::default()
+        }
+    }
+}
+
+/// Fills in a search's `SearchStats` as it runs. Without stats to fill it does nothing,
+/// and never reads the clock.
+pub(crate) struct Trace<'a> {
+    stats: Option<&'a mut SearchStats>,
+    started: Option<Instant>,
+}
+
+impl<'a> Trace<'a> {
+    pub(crate) fn new(stats: Option<&'a mut SearchStats>) -> Self {
+        let stats = stats.map(|stats| {
+            *stats = SearchStats::default();
+            stats
+        });
+        let started = stats.is_some().then(Instant::now);
+        Self { stats, started }
+    }
+
+    pub(crate) fn expand(&mut self, pos: GridCoord) {
+        if let Some(stats) = self.stats.as_deref_mut() {
+            stats.expanded += 1;
+            stats.explored.push(pos);
+        }
+    }
+
+    pub(crate) fn open_len(&mut self, len: usize) {
+        if let Some(stats) = self.stats.as_deref_mut() {
+            stats.open_peak = stats.open_peak.max(len);
+        }
+    }
+
+    /// Stats for a search run as one part of this one, or `None` when not tracing.
+    pub(crate) fn part(&self) -> Option<SearchStats> {
+        self.stats.is_some().then(SearchStats::default)
+    }
+
+    /// Count a part's nodes towards this search.
+    pub(crate) fn add(&mut self, part: Option<SearchStats>) {
+        if let (Some(stats), Some(part)) = (self.stats.as_deref_mut(), part) {
+            stats.expanded += part.expanded;
+            stats.open_peak = stats.open_peak.max(part.open_peak);
+            stats.explored.extend(part.explored);
+        }
+    }
+
+    pub(crate) fn finish(self, path_len: usize) {
+        if let (Some(stats), Some(started)) = (self.stats, self.started) {
+            stats.path_len = path_len;
+            stats.duration = started.elapsed();
+        }
+    }
+}
+
+/// Where an AI's move came from this turn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlanSource {
+    /// Random legal step
+    Wander,
+    /// Rest of an earlier path from its `PathCache`
+    Cached,
+    /// A* over a multi-tile footprint
+    AStar,
+    /// Hierarchical search on a large level
+    Hpa,
+    /// The shared chase flow field
+    FlowField,
+    /// Search over positions and held keys
+    Keyed,
+    /// Space-time search against other movers' reservations
+    Cooperative,
+    /// Downhill on the shared flee map
+    Flee,
+    /// Next step of a patrol
+    Patrol,
+    /// Walk to where the player was last detected
+    Search,
+    /// Opening step of the games played out by `plan_lookahead`
+    Lookahead,
+    /// Walk back to the goal a utility AI guards
+    Guard,
+    /// Walk to a key a utility AI means to pick up
+    Fetch,
+}
+
+/// One AI's plan for the current turn.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AiPlanTrace {
+    pub source: PlanSource,
+    pub path: Vec<GridCoord>,
+    /// Set when the AI ran its own A* search; flow-field movers share `PathDiagnostics::chase_field`.
+    pub stats: Option<SearchStats>,
+}
+
+/// Pathfinding diagnostics, refreshed by `plan_ai` every turn while `enabled`.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct PathDiagnostics {
+    pub enabled: bool,
+    pub plans: HashMap<Entity, AiPlanTrace>,
+    /// The flood behind this turn's chase flow field, if one was built.
+    pub chase_field: Option<SearchStats>,
+}
+
+impl PathDiagnostics {
+    pub fn record(&mut self, ai: Entity, source: PlanSource, path: &[GridCoord]) {
+        self.record_search(ai, source, path, None);
+    }
+
+    pub fn record_search(
+        &mut self,
+        ai: Entity,
+        source: PlanSource,
+        path: &[GridCoord],
+        stats: Option<SearchStats>,
+    ) {
+        if self.enabled {
+            let trace = AiPlanTrace {
+                source,
+                path: path.to_vec(),
+                stats,
+            };
+            self.plans.insert(ai, trace);
+        }
+    }
+
+    /// Stats for a search to fill in while enabled; `None` runs the search untraced.
+    pub fn new_stats(&self) -> Option<SearchStats> {
+        self.enabled.then(SearchStats::default)
+    }
+
+    /// Record a plan that reused `path` from the AI's cache: nothing was searched.
+    pub fn record_cached(&mut self, ai: Entity, path: &[GridCoord]) {
+        self.record_search(
+            ai,
+            PlanSource::Cached,
+            path,
+            Some(SearchStats::reused(path)),
+        );
+    }
+
+    /// Nodes expanded by every search this turn, the shared chase field included.
+    pub fn total_expanded(&self) -> usize {
+        let own: usize = self
+            .plans
+            .values()
+            .filter_map(|p| p.stats.as_ref())
+            .map(|s| s.expanded)
+            .sum();
+        own + self.chase_field.as_ref().map_or(0, |s| s.expanded)
+    }
+}
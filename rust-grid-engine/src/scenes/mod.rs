@@ -1,3 +1,4 @@
+use crate::ai::Difficulty;
 use crate::components::*;
 use crate::engine::TurnNumber;
 use crate::engine::replay::{
@@ -6,9 +7,10 @@ use crate::engine::replay::{
 };
 use crate::engine::rules::{GetCaught, ReachedGoal};
 use crate::engine::{RunSeed, TurnRng};
-use crate::grid::{GridCoord, GridTransform};
+use crate::grid::{GridCoord, GridTransform, OccupancyIndex};
 use crate::intents::Intent;
-use crate::map::load_level_from_json;
+use crate::map::{Level, load_level_from_json, validate_level};
+use crate::pathfinding::cooperative::{COOPERATIVE_WINDOW, CooperativePlanning};
 use bevy::asset::AssetServer;
 use bevy::image::Image;
 use bevy::prelude::*;
@@ -18,10 +20,14 @@ use std::fs;
 use std::process;
 use bevy::prelude::ClearColor;
 
+mod overlay;
+use overlay::{draw_path_overlay, toggle_path_overlay};
+
 #[derive(States, Debug, Clone, Copy, Eq, PartialEq, Hash, Default)]
 pub enum GameScene {
     #[default]
     Menu,
+    Settings,
     InGame,
     Replay,
     GameOver,
@@ -71,6 +77,28 @@ struct MainMenuSelection {
     index: usize,
 }
 
+// SETTINGS MENU
+
+#[derive(Component)]
+struct SettingsText;
+
+#[derive(Debug, Clone, Copy)]
+enum SettingsItemKind {
+    Difficulty,
+    Back,
+}
+
+#[derive(Component)]
+struct SettingsItem {
+    index: usize,
+    kind: SettingsItemKind,
+}
+
+#[derive(Resource, Default)]
+struct SettingsSelection {
+    index: usize,
+}
+
 // PAUSE MENU
 
 #[derive(Resource, Default)]
@@ -120,6 +148,35 @@ impl Default for LevelProgress {
     }
 }
 
+/// Levels that each show off one engine feature. They are not part of the campaign; start
+/// the game with `DEMO=1` to play through them instead.
+pub const DEMO_LEVELS: [&str; 6] = [
+    "assets/levels/horde.json",
+    "assets/levels/corridors.json",
+    "assets/levels/guards.json",
+    "assets/levels/maze.json",
+    "assets/levels/arena.json",
+    "assets/levels/graveyard.json",
+];
+
+impl LevelProgress {
+    /// The demo levels in place of the campaign.
+    pub fn demo() -> Self {
+        Self {
+            level_paths: DEMO_LEVELS.iter().map(|path| path.to_string()).collect(),
+            current: 0,
+        }
+    }
+}
+
+fn level_progress_on_boot() -> LevelProgress {
+    if std::env::var("DEMO").as_deref() == Ok("1") {
+        LevelProgress::demo()
+    } else {
+        LevelProgress::default()
+    }
+}
+
 // Pass level
 #[derive(Debug, Clone, Copy)]
 enum LevelCompleteItemKind {
@@ -183,9 +240,10 @@ impl Plugin for ScenePlugin {
             .insert_resource(GridTransform::default())
             .insert_resource(PauseState::default())
             .insert_resource(MainMenuSelection::default())
+            .insert_resource(SettingsSelection::default())
             .insert_resource(CurrentLevelName::default())
             .insert_resource(PauseMenuSelection::default())
-            .insert_resource(LevelProgress::default())
+            .insert_resource(level_progress_on_boot())
             .insert_resource(LevelCompleteSelection::default())
             .insert_resource(SaveSlot::default())
             .insert_resource(GameOverReason::default())
@@ -194,6 +252,9 @@ impl Plugin for ScenePlugin {
             // Menu enter/exit
             .add_systems(OnEnter(GameScene::Menu), setup_menu)
             .add_systems(OnExit(GameScene::Menu), teardown_menu)
+            // Settings enter/exit
+            .add_systems(OnEnter(GameScene::Settings), setup_settings)
+            .add_systems(OnExit(GameScene::Settings), teardown_settings)
             // InGame enter/exit
             .add_systems(
                 OnEnter(GameScene::InGame),
@@ -224,6 +285,9 @@ impl Plugin for ScenePlugin {
                 (
                     // MAIN MENU
                     (menu_input_system, update_menu_visuals).run_if(in_state(GameScene::Menu)),
+                    // SETTINGS MENU
+                    (settings_input_system, update_settings_visuals)
+                        .run_if(in_state(GameScene::Settings)),
                     // PAUSE MENU
                     (
                         pause_input_system,
@@ -236,6 +300,8 @@ impl Plugin for ScenePlugin {
                     update_level_complete_visuals,
                     // Game over input (in GameOver scene)
                     game_over_input_system.run_if(in_state(GameScene::GameOver)),
+                    // Path overlay (F3), shown in play and in replays
+                    (toggle_path_overlay, draw_path_overlay).run_if(is_in_game_scene),
                     // Replay overlay + finishing logic
                     (update_replay_overlay_fade, check_replay_finished)
                         .run_if(in_state(GameScene::Replay)),
@@ -357,7 +423,7 @@ fn menu_input_system(
                     }
                 }
                 MainMenuItemKind::Settings => {
-                    info!("Settings TBD");
+                    next.set(GameScene::Settings);
                 }
                 MainMenuItemKind::Exit => {
                     process::exit(0);
@@ -386,6 +452,129 @@ fn teardown_menu(mut commands: Commands, q: Query<Entity, With<MenuText>>) {
     }
 }
 
+// settings functions
+fn setup_settings(
+    mut commands: Commands,
+    mut selection: ResMut<SettingsSelection>,
+    difficulty: Res<Difficulty>,
+) {
+    selection.index = 0;
+
+    commands.spawn((
+        Text2d::new("Settings"),
+        TextFont::from_font_size(40.0),
+        TextColor(Color::WHITE),
+        Transform::from_xyz(0.0, 80.0, 10.0),
+        SettingsText,
+    ));
+    commands.spawn((
+        Text2d::new(difficulty_label(*difficulty)),
+        TextFont::from_font_size(28.0),
+        TextColor(Color::WHITE),
+        Transform::from_xyz(0.0, 20.0, 10.0),
+        SettingsText,
+        SettingsItem {
+            index: 0,
+            kind: SettingsItemKind::Difficulty,
+        },
+    ));
+    commands.spawn((
+        Text2d::new("Back"),
+        TextFont::from_font_size(28.0),
+        TextColor(Color::WHITE),
+        Transform::from_xyz(0.0, -20.0, 10.0),
+        SettingsText,
+        SettingsItem {
+            index: 1,
+            kind: SettingsItemKind::Back,
+        },
+    ));
+    commands.spawn((
+        Text2d::new("Left/Right to change, Esc to go back"),
+        TextFont::from_font_size(16.0),
+        TextColor(Color::srgb(0.8, 0.8, 0.8)),
+        Transform::from_xyz(0.0, -80.0, 10.0),
+        SettingsText,
+    ));
+}
+
+fn difficulty_label(difficulty: Difficulty) -> String {
+    format!("Difficulty: < {} >", difficulty.label())
+}
+
+fn settings_input_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut selection: ResMut<SettingsSelection>,
+    q_items: Query<&SettingsItem>,
+    mut difficulty: ResMut<Difficulty>,
+    mut next: ResMut<NextState<GameScene>>,
+) {
+    let max_index = q_items.iter().map(|c| c.index).max().unwrap_or(0);
+
+    // direction
+    if (keyboard.just_pressed(KeyCode::ArrowUp) || keyboard.just_pressed(KeyCode::KeyW))
+        && selection.index > 0
+    {
+        selection.index -= 1;
+    }
+    if (keyboard.just_pressed(KeyCode::ArrowDown) || keyboard.just_pressed(KeyCode::KeyS))
+        && selection.index < max_index
+    {
+        selection.index += 1;
+    }
+    if keyboard.just_pressed(KeyCode::Escape) {
+        next.set(GameScene::Menu);
+        return;
+    }
+
+    let left = keyboard.just_pressed(KeyCode::ArrowLeft) || keyboard.just_pressed(KeyCode::KeyA);
+    let right = keyboard.just_pressed(KeyCode::ArrowRight) || keyboard.just_pressed(KeyCode::KeyD);
+    let enter =
+        keyboard.just_pressed(KeyCode::Enter) || keyboard.just_pressed(KeyCode::NumpadEnter);
+    let Some(item) = q_items.iter().find(|c| c.index == selection.index) else {
+        return;
+    };
+    match item.kind {
+        SettingsItemKind::Difficulty => {
+            if left {
+                *difficulty = difficulty.previous();
+            } else if right || enter {
+                *difficulty = difficulty.next();
+            }
+        }
+        SettingsItemKind::Back => {
+            if enter {
+                next.set(GameScene::Menu);
+            }
+        }
+    }
+}
+
+fn update_settings_visuals(
+    selection: Res<SettingsSelection>,
+    difficulty: Res<Difficulty>,
+    mut q: Query<(&SettingsItem, &mut TextColor, &mut Text2d)>,
+) {
+    for (item, mut color, mut text) in &mut q {
+        color.0 = if item.index == selection.index {
+            Color::srgb(1.0, 1.0, 0.0)
+        } else {
+            Color::WHITE
+        };
+        if let SettingsItemKind::Difficulty = item.kind
+            && difficulty.is_changed()
+        {
+            text.0 = difficulty_label(*difficulty);
+        }
+    }
+}
+
+fn teardown_settings(mut commands: Commands, q: Query<Entity, With<SettingsText>>) {
+    for e in &q {
+        commands.entity(e).despawn();
+    }
+}
+
 fn setup_game(
     mut commands: Commands,
     grid_tf: Res<GridTransform>,
@@ -393,6 +582,7 @@ fn setup_game(
     progress: Res<LevelProgress>,
     sprite_assets: Res<SpriteAssets>,
     mut current_name: ResMut<CurrentLevelName>,
+    mut occ: ResMut<OccupancyIndex>,
 ) {
     commands.spawn((
         Sprite {
@@ -409,6 +599,7 @@ fn setup_game(
         &progress,
         &sprite_assets,
         &mut current_name,
+        &mut occ,
     );
 }
 
@@ -419,6 +610,7 @@ fn spawn_current_level(
     progress: &LevelProgress,
     sprite_assets: &SpriteAssets,
     current_name: &mut CurrentLevelName,
+    occ: &mut OccupancyIndex,
 ) {
     // Reset per-run state
     turn.0 = 0;
@@ -435,113 +627,194 @@ fn spawn_current_level(
     let level = load_level_from_json(&bytes).expect("invalid level JSON");
     info!("Loaded level path: {path}");
     info!("Parsed JSON name field: {:?}", level.name);
+    if let Err(e) = validate_level(&level) {
+        warn!("Level {path} failed validation: {e}");
+    }
     current_name.0 = level
         .name
         .clone()
         .unwrap_or_else(|| current_level_label(progress));
+    spawn_level(
+        commands,
+        occ,
+        level,
+        Some(&LevelArt {
+            grid_tf,
+            sprites: sprite_assets,
+        }),
+    );
+}
+
+// How a spawned level is drawn; headless runs spawn one without any
+pub struct LevelArt<'a> {
+    pub grid_tf: &'a GridTransform,
+    pub sprites: &'a SpriteAssets,
+}
+
+impl LevelArt<'_> {
+    fn tile(&self, image: &Handle<Image>, at: GridCoord) -> (Sprite, Transform) {
+        (
+            Sprite {
+                image: image.clone(),
+                custom_size: Some(Vec2::splat(self.grid_tf.tile_size)),
+                ..Default::default()
+            },
+            Transform::from_translation(self.grid_tf.to_world(at)),
+        )
+    }
+}
+
+/// Spawn a level's entities and point the grid resources at it. Everything the turn
+/// pipeline reads is spawned whether or not `art` is given, so tests can run real levels
+pub fn spawn_level(
+    commands: &mut Commands,
+    occ: &mut OccupancyIndex,
+    level: Level,
+    art: Option<&LevelArt>,
+) {
+    occ.set_bounds(Some(level.bounds()));
+    commands.insert_resource(level.movement);
+    commands.insert_resource(CooperativePlanning {
+        window: level.cooperative.then_some(COOPERATIVE_WINDOW),
+    });
+    commands.insert_resource(level.search);
 
     // player
     let p = level.player_start;
-    commands.spawn((
+    let mut player = commands.spawn((
         Player,
         Actor,
         Position(p),
         PendingIntent(Intent::Wait),
-        Sprite {
-            image: sprite_assets.player.clone(),
-            custom_size: Some(Vec2::splat(grid_tf.tile_size)),
-            ..Default::default()
-        },
-        Transform::from_translation(grid_tf.to_world(p)),
+        Facing::default(),
     ));
+    if level.player_has_key_ring() {
+        player.insert(KeyRing::default());
+    }
+    if let Some(art) = art {
+        player.insert(art.tile(&art.sprites.player, p));
+    }
 
     //walls
     for w in level.walls {
-        commands.spawn((
-            Blocking,
-            Position(w),
-            Sprite {
-                image: sprite_assets.wall.clone(),
-                custom_size: Some(Vec2::splat(grid_tf.tile_size)),
-                ..Default::default()
-            },
-            Transform::from_translation(grid_tf.to_world(w)),
-        ));
+        let mut wall = commands.spawn((Blocking, Position(w)));
+        if let Some(art) = art {
+            wall.insert(art.tile(&art.sprites.wall, w));
+        }
     }
 
     // goals
     for g in level.goals {
-        commands.spawn((
-            Goal,
-            Position(g),
-            Sprite {
-                image: sprite_assets.goal.clone(),
-                custom_size: Some(Vec2::splat(grid_tf.tile_size)),
-                ..Default::default()
-            },
-            Transform::from_translation(grid_tf.to_world(g)),
-        ));
+        let mut goal = commands.spawn((Goal, Position(g)));
+        if let Some(art) = art {
+            goal.insert(art.tile(&art.sprites.goal, g));
+        }
     }
 
     // traps
     for t in level.traps {
-        commands.spawn((
-            Trap,
-            Position(t),
-            Sprite {
-                image: sprite_assets.trap.clone(),
-                custom_size: Some(Vec2::splat(grid_tf.tile_size)),
-                ..Default::default()
-            },
-            Transform::from_translation(grid_tf.to_world(t)),
-        ));
+        let mut trap = commands.spawn((Trap, Position(t)));
+        if let Some(art) = art {
+            trap.insert(art.tile(&art.sprites.trap, t));
+        }
     }
 
     // doors
     for d in level.doors {
         let coord = GridCoord::new(d.x, d.y);
-        commands.spawn((
-            Door,
-            Blocking,
-            Position(coord),
-            Sprite {
-                image: if d.locked {
-                    sprite_assets.door_locked.clone()
-                } else {
-                    sprite_assets.door_unlocked.clone()
-                },
-                custom_size: Some(Vec2::splat(grid_tf.tile_size)),
-                ..Default::default()
-            },
-            Transform::from_translation(grid_tf.to_world(coord)),
-        ));
+        let mut door = commands.spawn((Door, Blocking, Position(coord)));
+        if d.locked {
+            door.insert(Lock(d.key_id));
+        }
+        if let Some(art) = art {
+            let image = if d.locked {
+                &art.sprites.door_locked
+            } else {
+                &art.sprites.door_unlocked
+            };
+            door.insert(art.tile(image, coord));
+        }
     }
 
-    // enemies
+    // keys
+    for k in level.keys {
+        let coord = GridCoord::new(k.x, k.y);
+        let mut key = commands.spawn((Key(k.key_id), Position(coord)));
+        if let Some(art) = art {
+            let (mut sprite, transform) = art.tile(&art.sprites.goal, coord);
+            sprite.color = Color::srgb(1.0, 0.85, 0.2);
+            sprite.custom_size = Some(Vec2::splat(art.grid_tf.tile_size * 0.6));
+            key.insert((sprite, transform));
+        }
+    }
+
+    // enemies; targeting is added once they all exist, as it can point at another enemy
+    let mut spawned = Vec::new();
+    let mut targeted = Vec::new();
     for e in level.enemies {
         let coord = GridCoord::new(e.x, e.y);
-        commands.spawn((
+        let footprint = e.footprint.unwrap_or_default();
+        let mut enemy = commands.spawn((
             Actor,
             AI,
             Blocking,
             Position(coord),
             PendingIntent(Intent::Wait),
-            Sprite {
-                image: sprite_assets.enemy.clone(),
-                custom_size: Some(Vec2::splat(grid_tf.tile_size)),
-                ..Default::default()
-            },
-            Transform::from_translation(grid_tf.to_world(coord)),
+            PathCache::default(),
+            AiKind(e.kind),
+            Facing::default(),
         ));
+        if let Some(art) = art {
+            enemy.insert((
+                Sprite {
+                    image: art.sprites.enemy.clone(),
+                    custom_size: Some(
+                        Vec2::new(footprint.width as f32, footprint.height as f32)
+                            * art.grid_tf.tile_size,
+                    ),
+                    ..Default::default()
+                },
+                Transform::from_translation(art.grid_tf.footprint_to_world(coord, footprint)),
+            ));
+        }
+        if !footprint.is_single() {
+            enemy.insert(footprint);
+        }
+        if e.opens_doors {
+            enemy.insert(KeyRing::default());
+        }
+        if let Some(patrol) = e.patrol {
+            enemy.insert(Patrol::new(patrol.waypoints, patrol.mode));
+        }
+        if let Some(perception) = e.perception {
+            enemy.insert((perception, Awareness::default()));
+        }
+        if let Some(lookahead) = e.lookahead {
+            enemy.insert(lookahead);
+        }
+        // a level that failed validation still plays; out-of-range speeds are pulled into it
+        if let Some(Speed(speed)) = e.speed {
+            enemy.insert(Speed(speed.clamp(1, MAX_SPEED)));
+        }
+        if let Some(targeting) = e.targeting {
+            targeted.push((enemy.id(), targeting));
+        }
+        spawned.push(enemy.id());
+    }
+    for (enemy, targeting) in targeted {
+        commands.entity(enemy).insert(targeting.resolve(&spawned));
     }
 }
 
 pub fn sync_transforms(
     grid_transform: Res<GridTransform>,
-    mut q: Query<(&Position, &mut Transform)>,
+    mut q: Query<(&Position, Option<&Footprint>, &mut Transform)>,
 ) {
-    for (pos, mut transform) in &mut q {
-        transform.translation = grid_transform.to_world(pos.0);
+    for (pos, footprint, mut transform) in &mut q {
+        transform.translation = match footprint {
+            Some(fp) => grid_transform.footprint_to_world(pos.0, *fp),
+            None => grid_transform.to_world(pos.0),
+        };
     }
 }
 
@@ -915,6 +1188,7 @@ fn level_complete_navigation_system(
     mut turn: ResMut<TurnNumber>,
     sprite_assets: Res<SpriteAssets>,
     mut current_name: ResMut<CurrentLevelName>,
+    mut occ: ResMut<OccupancyIndex>,
 ) {
     // Only run if the window is visible
     if q_roots.is_empty() {
@@ -963,6 +1237,7 @@ fn level_complete_navigation_system(
                             &progress,
                             &sprite_assets,
                             &mut current_name,
+                            &mut occ,
                         );
                     } else {
                         // No more levels: go to GameOver scene
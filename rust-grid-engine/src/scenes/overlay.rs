@@ -0,0 +1,72 @@
+use crate::grid::GridTransform;
+use crate::pathfinding::stats::{PathDiagnostics, PlanSource};
+use bevy::prelude::*;
+
+/// F3 turns the path overlay on and off. Diagnostics are only gathered while it is on.
+pub fn toggle_path_overlay(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut diagnostics: ResMut<PathDiagnostics>,
+) {
+    if keyboard.just_pressed(KeyCode::F3) {
+        diagnostics.enabled = !diagnostics.enabled;
+        if !diagnostics.enabled {
+            diagnostics.plans.clear();
+            diagnostics.chase_field = None;
+        }
+    }
+}
+
+/// Draws the cells each AI's search expanded this turn, faintly, and the path it chose on top,
+/// coloured by where the plan came from.
+pub fn draw_path_overlay(
+    diagnostics: Res<PathDiagnostics>,
+    grid_tf: Res<GridTransform>,
+    mut gizmos: Gizmos,
+) {
+    if !diagnostics.enabled {
+        return;
+    }
+    let centre = |c| grid_tf.to_world(c).truncate();
+    let cell = Vec2::splat(grid_tf.tile_size * 0.9);
+
+    let explored = diagnostics
+        .chase_field
+        .iter()
+        .chain(diagnostics.plans.values().filter_map(|p| p.stats.as_ref()))
+        .flat_map(|s| s.explored.iter());
+    for &c in explored {
+        gizmos.rect_2d(
+            Isometry2d::from_translation(centre(c)),
+            cell,
+            Color::srgba(0.3, 0.6, 1.0, 0.25),
+        );
+    }
+
+    for plan in diagnostics.plans.values() {
+        let color = match plan.source {
+            PlanSource::Wander => Color::srgb(0.7, 0.7, 0.7),
+            PlanSource::Cached => Color::srgb(0.4, 0.9, 0.4),
+            PlanSource::AStar => Color::srgb(1.0, 0.5, 0.1),
+            PlanSource::Hpa => Color::srgb(0.8, 0.3, 1.0),
+            PlanSource::FlowField => Color::srgb(0.2, 0.8, 1.0),
+            PlanSource::Keyed => Color::srgb(1.0, 0.85, 0.2),
+            PlanSource::Cooperative => Color::srgb(1.0, 0.3, 0.6),
+            PlanSource::Flee => Color::srgb(1.0, 1.0, 1.0),
+            PlanSource::Patrol => Color::srgb(0.3, 0.3, 1.0),
+            PlanSource::Search => Color::srgb(0.9, 0.9, 0.3),
+            PlanSource::Lookahead => Color::srgb(1.0, 0.1, 0.1),
+            PlanSource::Guard => Color::srgb(0.1, 0.6, 0.3),
+            PlanSource::Fetch => Color::srgb(0.6, 0.4, 0.1),
+        };
+        // steps across a wrapped edge are left out rather than drawn across the whole map
+        for step in plan.path.windows(2) {
+            let (a, b) = (step[0], step[1]);
+            if (a.x - b.x).abs() <= 1 && (a.y - b.y).abs() <= 1 {
+                gizmos.line_2d(centre(a), centre(b), color);
+            }
+        }
+        if let Some(&end) = plan.path.last() {
+            gizmos.circle_2d(Isometry2d::from_translation(centre(end)), 4.0, color);
+        }
+    }
+}
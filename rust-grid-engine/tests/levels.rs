@@ -0,0 +1,281 @@
+use std::collections::HashSet;
+use std::fs;
+
+use bevy::ecs::system::RunSystemOnce;
+use bevy::prelude::*;
+use rust_grid_engine::components::{Footprint, KeyRing, Player};
+use rust_grid_engine::engine::rules::{DefaultRules, MoveCheck, check_step};
+use rust_grid_engine::grid::{
+    Dir, GridBounds, GridCoord, MovementMode, OccupancyIndex, RegionKind, RegionMap,
+    rebuild_occupancy,
+};
+use rust_grid_engine::map::{Level, load_level_from_json, validate_level};
+use rust_grid_engine::scenes::spawn_level;
+
+#[test]
+fn shipped_levels_validate() {
+    for entry in fs::read_dir("assets/levels").expect("missing assets/levels") {
+        let path = entry.unwrap().path();
+        let bytes = fs::read(&path).unwrap();
+        let level = load_level_from_json(&bytes).expect("invalid level JSON");
+        if let Err(e) = validate_level(&level) {
+            panic!("{} failed validation: {e}", path.display());
+        }
+    }
+}
+
+#[test]
+fn regions_split_rooms_corridors_and_doors() {
+    // Two 3x3 rooms joined by a corridor at y = 1, with a door where it meets the right room:
+    //
+    //   ...#####...
+    //   ....+......   (x = 3..=6 corridor, door at x = 7)
+    //   ...#####...
+    let bounds = GridBounds::new(11, 3);
+    let mut walls = HashSet::new();
+    for x in 3..8 {
+        walls.insert(GridCoord::new(x, 0));
+        walls.insert(GridCoord::new(x, 2));
+    }
+    let doors = HashSet::from([GridCoord::new(7, 1)]);
+
+    let map = RegionMap::build(bounds, MovementMode::FourWay, &walls, &doors);
+
+    let left = map.room_at(GridCoord::new(1, 1)).expect("left room");
+    let right = map.room_at(GridCoord::new(9, 1)).expect("right room");
+    let corridor = map.region_at(GridCoord::new(5, 1)).expect("corridor");
+    assert_ne!(left, right);
+    assert_eq!(map.region(corridor).unwrap().kind, RegionKind::Corridor);
+    assert_eq!(map.room_at(GridCoord::new(5, 1)), None);
+
+    assert_eq!(map.adjacent(corridor), vec![left, right]);
+    assert!(
+        map.links()
+            .iter()
+            .any(|l| l.is_door && l.cell == GridCoord::new(7, 1))
+    );
+    assert!(map.connected(GridCoord::new(0, 0), GridCoord::new(10, 2)));
+}
+
+#[test]
+fn regions_join_through_diagonal_gaps_on_eight_way_maps() {
+    // A wall staircase leaves only a diagonal squeeze from (1, 1) to (2, 2):
+    //
+    //   ....
+    //   ..##
+    //   ##..   (y = 1)
+    //   ....
+    let walls = [(0, 2), (1, 2), (2, 1), (3, 1)];
+    let json = |movement: &str| {
+        format!(
+            r#"{{
+                "width": 4, "height": 4, "movement": "{movement}",
+                "player_start": {{ "x": 0, "y": 0 }},
+                "walls": [{}],
+                "goals": [{{ "x": 3, "y": 3 }}]
+            }}"#,
+            walls
+                .map(|(x, y)| format!(r#"{{ "x": {x}, "y": {y} }}"#))
+                .join(", ")
+        )
+    };
+    let walls: HashSet<GridCoord> = walls.map(|(x, y)| GridCoord::new(x, y)).into();
+    let (near, far) = (GridCoord::new(0, 0), GridCoord::new(3, 3));
+
+    for (movement, name, joined) in [
+        (MovementMode::FourWay, "four_way", false),
+        (MovementMode::EightWay, "eight_way", true),
+        (
+            MovementMode::EightWayNoCornerCut,
+            "eight_way_no_corner_cut",
+            false,
+        ),
+    ] {
+        let map = RegionMap::build(GridBounds::new(4, 4), movement, &walls, &HashSet::new());
+        assert_eq!(map.connected(near, far), joined, "{name}");
+        let level = load_level_from_json(json(name).as_bytes()).expect("invalid level JSON");
+        assert_eq!(validate_level(&level).is_ok(), joined, "{name}");
+    }
+}
+
+#[test]
+fn validator_checks_every_cell_of_a_large_enemy() {
+    // A 2x2 enemy anchored at (1, 1) on a 6x4 map with a wall at (3, 2)
+    let level = |x: i32| {
+        let json = format!(
+            r#"{{
+                "width": 6, "height": 4,
+                "player_start": {{ "x": 0, "y": 0 }},
+                "walls": [{{ "x": 3, "y": 2 }}],
+                "goals": [],
+                "enemies": [{{ "x": {x}, "y": 1, "kind": "chaser",
+                    "footprint": {{ "width": 2, "height": 2 }} }}]
+            }}"#
+        );
+        load_level_from_json(json.as_bytes()).expect("invalid level JSON")
+    };
+    assert!(validate_level(&level(1)).is_ok());
+    // the anchor is free but the top-right cell is the wall
+    assert!(validate_level(&level(2)).is_err());
+    // the right-hand column sticks out of the map
+    assert!(validate_level(&level(5)).is_err());
+}
+
+#[test]
+fn validator_requires_keys_for_locked_doors() {
+    // A 7x3 strip, wall column at x = 3 with a locked door in the middle; goal on the far side
+    let level = |keys: &str| {
+        let json = format!(
+            r#"{{
+                "width": 7, "height": 3,
+                "player_start": {{ "x": 0, "y": 1 }},
+                "walls": [{{ "x": 3, "y": 0 }}, {{ "x": 3, "y": 2 }}],
+                "goals": [{{ "x": 6, "y": 1 }}],
+                "doors": [{{ "x": 3, "y": 1, "locked": true, "key_id": 2 }}],
+                "keys": [{keys}]
+            }}"#
+        );
+        load_level_from_json(json.as_bytes()).expect("invalid level JSON")
+    };
+
+    assert!(validate_level(&level("")).is_err());
+    assert!(validate_level(&level(r#"{ "x": 0, "y": 0, "key_id": 1 }"#)).is_err());
+    // the key behind its own door does not help
+    assert!(validate_level(&level(r#"{ "x": 5, "y": 0, "key_id": 2 }"#)).is_err());
+    assert!(validate_level(&level(r#"{ "x": 0, "y": 0, "key_id": 2 }"#)).is_ok());
+    assert!(validate_level(&level(r#"{ "x": 0, "y": 0, "key_id": 64 }"#)).is_err());
+}
+
+// `level` spawned into a fresh world with its occupancy built; returns the world and the player
+fn spawned(level: Level) -> (World, Entity) {
+    let mut world = World::new();
+    world.init_resource::<OccupancyIndex>();
+    world
+        .run_system_once(
+            move |mut commands: Commands, mut occ: ResMut<OccupancyIndex>| {
+                spawn_level(&mut commands, &mut occ, level.clone(), None);
+            },
+        )
+        .unwrap();
+    world.run_system_once(rebuild_occupancy).unwrap();
+    let player = world
+        .query_filtered::<Entity, With<Player>>()
+        .single(&world)
+        .unwrap();
+    (world, player)
+}
+
+// Whether the player may step onto `door` from the cell to its left
+fn player_opens(world: &World, player: Entity, door: GridCoord) -> bool {
+    let from = GridCoord::new(door.x - 1, door.y);
+    let occ = world.resource::<OccupancyIndex>();
+    let check = check_step(
+        &DefaultRules,
+        occ,
+        MovementMode::FourWay,
+        player,
+        Footprint::SINGLE,
+        from,
+        Dir::Right,
+    );
+    matches!(check, MoveCheck::Allow)
+}
+
+#[test]
+fn players_only_carry_a_key_ring_on_levels_with_keys_or_locks() {
+    // Shipped levels without keys keep every door shut to the player
+    for entry in fs::read_dir("assets/levels").expect("missing assets/levels") {
+        let path = entry.unwrap().path();
+        let level = load_level_from_json(&fs::read(&path).unwrap()).expect("invalid level JSON");
+        if !level.keys.is_empty() {
+            continue;
+        }
+        let doors: Vec<GridCoord> = level
+            .doors
+            .iter()
+            .map(|d| GridCoord::new(d.x, d.y))
+            .collect();
+        let (world, player) = spawned(level);
+        for door in doors {
+            assert!(
+                !player_opens(&world, player, door),
+                "{}: the player opens the door at {door:?}",
+                path.display()
+            );
+        }
+    }
+
+    // An unlocked door only opens for a player given a ring by a key somewhere on the level
+    let level = |keys: &str| {
+        let json = format!(
+            r#"{{
+                "width": 7, "height": 3,
+                "player_start": {{ "x": 0, "y": 1 }},
+                "walls": [{{ "x": 3, "y": 0 }}, {{ "x": 3, "y": 2 }}],
+                "goals": [{{ "x": 6, "y": 1 }}],
+                "doors": [{{ "x": 3, "y": 1, "locked": false, "key_id": 0 }}],
+                "keys": [{keys}]
+            }}"#
+        );
+        load_level_from_json(json.as_bytes()).expect("invalid level JSON")
+    };
+    let door = GridCoord::new(3, 1);
+    for (keys, ring) in [("", false), (r#"{ "x": 0, "y": 0, "key_id": 1 }"#, true)] {
+        let level = level(keys);
+        assert_eq!(level.player_has_key_ring(), ring);
+        assert_eq!(validate_level(&level).is_ok(), ring);
+        let (world, player) = spawned(level);
+        assert_eq!(world.get::<KeyRing>(player).is_some(), ring);
+        assert_eq!(player_opens(&world, player, door), ring);
+    }
+}
+
+#[test]
+fn bounds_block_the_map_edge_unless_it_wraps() {
+    let mut occ = OccupancyIndex::default();
+    occ.set_bounds(Some(GridBounds::new(5, 3)));
+    let corner = GridCoord::new(4, 2);
+    let step = |occ: &OccupancyIndex, dir| {
+        let mover = Entity::from_raw_u32(1).unwrap();
+        let footprint = Footprint::SINGLE;
+        check_step(
+            &DefaultRules,
+            occ,
+            MovementMode::FourWay,
+            mover,
+            footprint,
+            corner,
+            dir,
+        )
+    };
+    assert!(occ.in_bounds(corner));
+    assert!(!occ.in_bounds(GridCoord::new(5, 2)));
+    assert!(matches!(step(&occ, Dir::Right), MoveCheck::Blocked));
+    assert!(matches!(step(&occ, Dir::Up), MoveCheck::Blocked));
+    assert!(matches!(step(&occ, Dir::Left), MoveCheck::Allow));
+
+    occ.set_bounds(Some(GridBounds::new(5, 3).wrapping(true)));
+    assert_eq!(occ.step(corner, Dir::Right), GridCoord::new(0, 2));
+    assert_eq!(occ.step(corner, Dir::Up), GridCoord::new(4, 0));
+    assert_eq!(occ.normalize(GridCoord::new(-1, -1)), corner);
+    assert_eq!(occ.delta(GridCoord::new(0, 0), corner), (-1, -1));
+    assert!(matches!(step(&occ, Dir::Right), MoveCheck::Allow));
+}
+
+#[test]
+fn validator_rejects_walls_outside_the_map() {
+    let level = |wall: &str| {
+        let json = format!(
+            r#"{{
+                "width": 4, "height": 2,
+                "player_start": {{ "x": 0, "y": 0 }},
+                "walls": [{wall}],
+                "goals": []
+            }}"#
+        );
+        load_level_from_json(json.as_bytes()).expect("invalid level JSON")
+    };
+    assert!(validate_level(&level(r#"{ "x": 3, "y": 1 }"#)).is_ok());
+    assert!(validate_level(&level(r#"{ "x": -1, "y": 0 }"#)).is_err());
+    assert!(validate_level(&level(r#"{ "x": 0, "y": 2 }"#)).is_err());
+}
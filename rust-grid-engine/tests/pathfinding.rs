@@ -0,0 +1,619 @@
+use std::collections::HashSet;
+
+use bevy::prelude::{Entity, World};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use rust_grid_engine::components::Footprint;
+use rust_grid_engine::engine::rules::{DefaultRules, MoveCheck, Rules, check_step};
+use rust_grid_engine::grid::{Dir, GridBounds, GridCoord, Layer, MovementMode, OccupancyIndex};
+use rust_grid_engine::pathfinding::astar::{
+    ORTHOGONAL_COST, PathResult, SearchAlgorithm, astar, astar_footprint, astar_footprint_traced,
+    astar_partial, find_path, step_cost,
+};
+use rust_grid_engine::pathfinding::dijkstra::DijkstraMap;
+use rust_grid_engine::pathfinding::flow::FlowField;
+use rust_grid_engine::pathfinding::hpa::HpaGraph;
+use rust_grid_engine::pathfinding::jps::{jps, jps_partial, jps_traced};
+use rust_grid_engine::pathfinding::keys::{KeyLayout, KeySet, keyed_path, keyed_path_traced};
+use rust_grid_engine::pathfinding::policy::{PathPolicy, RulesPolicy};
+use rust_grid_engine::pathfinding::stats::SearchStats;
+
+// A grid of walls with `step_cost` steps, searched without a world
+struct WallPolicy {
+    walls: HashSet<GridCoord>,
+    bounds: GridBounds,
+    movement: MovementMode,
+    algorithm: SearchAlgorithm,
+    max_expansions: Option<usize>,
+}
+
+impl PathPolicy for WallPolicy {
+    fn passable(&self, c: GridCoord) -> bool {
+        !self.walls.contains(&c)
+    }
+
+    fn bounds(&self) -> Option<GridBounds> {
+        Some(self.bounds)
+    }
+
+    fn movement(&self) -> MovementMode {
+        self.movement
+    }
+
+    fn algorithm(&self) -> SearchAlgorithm {
+        self.algorithm
+    }
+
+    fn max_expansions(&self) -> Option<usize> {
+        self.max_expansions
+    }
+}
+
+fn policy(walls: &[GridCoord], bounds: GridBounds, movement: MovementMode) -> WallPolicy {
+    WallPolicy {
+        walls: walls.iter().copied().collect(),
+        bounds,
+        movement,
+        algorithm: SearchAlgorithm::AStar,
+        max_expansions: None,
+    }
+}
+
+fn path_cost(path: &[GridCoord]) -> u32 {
+    path.windows(2).map(|w| step_cost(w[0], w[1])).sum()
+}
+
+#[test]
+fn astar_stays_inside_bounds() {
+    // Wall across the map except the column just outside it.
+    let walls: Vec<GridCoord> = (0..5).map(|y| GridCoord::new(2, y)).collect();
+    let p = policy(&walls, GridBounds::new(5, 5), MovementMode::FourWay);
+
+    assert!(astar(GridCoord::new(0, 2), GridCoord::new(4, 2), &p).is_none());
+}
+
+#[test]
+fn astar_uses_wrap_around_tunnel() {
+    let walls: Vec<GridCoord> = (0..5).map(|y| GridCoord::new(2, y)).collect();
+    let p = policy(
+        &walls,
+        GridBounds::new(5, 5).wrapping(true),
+        MovementMode::FourWay,
+    );
+
+    let path = astar(GridCoord::new(0, 2), GridCoord::new(4, 2), &p).expect("tunnel path");
+    assert_eq!(path, vec![GridCoord::new(0, 2), GridCoord::new(4, 2)]);
+}
+
+#[test]
+fn astar_diagonals_respect_corner_cutting() {
+    // Two walls touching at a corner: only corner-cutting movement can slip between them.
+    let walls = [GridCoord::new(1, 0), GridCoord::new(0, 1)];
+    let start = GridCoord::new(0, 0);
+    let goal = GridCoord::new(1, 1);
+
+    let cut = policy(&walls, GridBounds::new(3, 3), MovementMode::EightWay);
+    assert_eq!(astar(start, goal, &cut).map(|p| p.len()), Some(2));
+
+    let no_cut = policy(
+        &walls,
+        GridBounds::new(3, 3),
+        MovementMode::EightWayNoCornerCut,
+    );
+    assert!(astar(start, goal, &no_cut).is_none());
+}
+
+#[test]
+fn astar_footprint_fits_through_wide_gaps_only() {
+    // A wall at x = 3 with a one-cell gap at y = 1 and a two-cell gap at y = 4..=5.
+    let walls: Vec<GridCoord> = (0..8)
+        .filter(|y| ![1, 4, 5].contains(y))
+        .map(|y| GridCoord::new(3, y))
+        .collect();
+    let p = policy(&walls, GridBounds::new(8, 8), MovementMode::FourWay);
+    let boss = Footprint {
+        width: 2,
+        height: 2,
+    };
+
+    let path = astar_footprint(GridCoord::new(0, 0), GridCoord::new(6, 1), boss, &p)
+        .expect("2x2 path through the wide gap");
+    assert!(path.iter().any(|a| a.x == 2 && a.y == 4));
+    let last = *path.last().unwrap();
+    assert!(boss.cells(last).any(|c| c == GridCoord::new(6, 1)));
+}
+
+#[test]
+fn dijkstra_map_matches_astar_from_nearest_goal() {
+    let walls: Vec<GridCoord> = (0..6).map(|y| GridCoord::new(3, y)).collect();
+    let p = policy(&walls, GridBounds::new(8, 8), MovementMode::EightWay);
+    let goals = [GridCoord::new(0, 0), GridCoord::new(7, 7)];
+    let field = DijkstraMap::build(goals, &p, i32::MAX);
+
+    for cell in [
+        GridCoord::new(1, 5),
+        GridCoord::new(5, 1),
+        GridCoord::new(2, 7),
+    ] {
+        let best = goals
+            .iter()
+            .filter_map(|&g| astar(cell, g, &p))
+            .map(|path| path_cost(&path))
+            .min()
+            .unwrap();
+        assert_eq!(field.get(cell), Some(best as i32));
+    }
+    assert_eq!(field.get(GridCoord::new(3, 0)), None);
+
+    // Walking downhill from anywhere ends on a goal
+    let mut at = GridCoord::new(5, 1);
+    while let Some(next) = field.next_step(at, &p) {
+        at = next;
+    }
+    assert!(goals.contains(&at));
+}
+
+#[test]
+fn dijkstra_flee_map_leads_away_from_threat() {
+    let p = policy(&[], GridBounds::new(9, 1), MovementMode::FourWay);
+    let threat = DijkstraMap::build([GridCoord::new(2, 0)], &p, i32::MAX);
+    let flee = threat.inverted(1.2, &p, i32::MAX);
+
+    assert_eq!(
+        flee.next_step(GridCoord::new(3, 0), &p),
+        Some(GridCoord::new(4, 0))
+    );
+    assert_eq!(
+        flee.next_step(GridCoord::new(1, 0), &p),
+        Some(GridCoord::new(0, 0))
+    );
+
+    let toward_exit = DijkstraMap::build([GridCoord::new(8, 0)], &p, i32::MAX);
+    let combined = DijkstraMap::combine(&[(&threat, 1.0), (&toward_exit, 2.0)]);
+    assert_eq!(
+        combined.next_step(GridCoord::new(2, 0), &p),
+        Some(GridCoord::new(3, 0))
+    );
+}
+
+#[test]
+fn jps_matches_astar_path_lengths() {
+    let mut rng = StdRng::seed_from_u64(7);
+    for movement in [
+        MovementMode::FourWay,
+        MovementMode::EightWay,
+        MovementMode::EightWayNoCornerCut,
+    ] {
+        for wrap in [false, true] {
+            for _ in 0..40 {
+                let bounds = GridBounds::new(16, 12).wrapping(wrap);
+                let walls: Vec<GridCoord> = (0..16)
+                    .flat_map(|x| (0..12).map(move |y| GridCoord::new(x, y)))
+                    .filter(|_| rng.gen_bool(0.25))
+                    .collect();
+                let mut p = policy(&walls, bounds, movement);
+                let start = GridCoord::new(rng.gen_range(0..16), rng.gen_range(0..12));
+                let goal = GridCoord::new(rng.gen_range(0..16), rng.gen_range(0..12));
+                if walls.contains(&start) || walls.contains(&goal) {
+                    continue;
+                }
+
+                let expected = astar(start, goal, &p);
+                let jumped = jps(start, goal, &p);
+                assert_eq!(
+                    expected.as_deref().map(path_cost),
+                    jumped.as_deref().map(path_cost),
+                    "{movement:?} wrap={wrap} {start:?} -> {goal:?}"
+                );
+
+                // Every cell is walked, one legal step at a time
+                if let Some(path) = jumped {
+                    assert_eq!((path[0], *path.last().unwrap()), (start, goal));
+                    assert!(path.iter().all(|c| !walls.contains(c)));
+                    let astar_steps = astar(path[0], path[1], &p).map(|s| s.len());
+                    assert!(path.len() == 1 || astar_steps == Some(2));
+                }
+
+                p.algorithm = SearchAlgorithm::JumpPoint;
+                assert_eq!(
+                    expected.as_deref().map(path_cost),
+                    find_path(start, goal, &p).as_deref().map(path_cost)
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn jps_honours_budget_and_reports_stats() {
+    // An open field with one wall across most of it: JPS jumps where A* expands cell by cell
+    let walls: Vec<GridCoord> = (0..14).map(|y| GridCoord::new(15, y)).collect();
+    let mut p = policy(&walls, GridBounds::new(30, 20), MovementMode::EightWay);
+    let (start, goal) = (GridCoord::new(2, 3), GridCoord::new(27, 4));
+
+    let mut astar_stats = SearchStats::default();
+    let expected = astar_footprint_traced(start, goal, Footprint::SINGLE, &p, &mut astar_stats);
+    p.algorithm = SearchAlgorithm::JumpPoint;
+    let mut stats = SearchStats::default();
+    let jumped = jps_traced(start, goal, &p, &mut stats);
+    assert!(jumped.is_complete());
+    assert_eq!(path_cost(jumped.path()), path_cost(expected.path()));
+    assert_eq!(
+        jumped,
+        jps_partial(start, goal, &p),
+        "tracing does not change the result"
+    );
+    assert_eq!(stats.path_len, jumped.path().len());
+    assert_eq!(stats.expanded, stats.explored.len());
+    assert_eq!(stats.explored[0], start);
+    assert!(stats.open_peak > 0);
+    assert!(stats.expanded * 4 < astar_stats.expanded);
+
+    // Single-tile footprint searches run the policy's algorithm
+    let mut footprint_stats = SearchStats::default();
+    let via_footprint =
+        astar_footprint_traced(start, goal, Footprint::SINGLE, &p, &mut footprint_stats);
+    assert_eq!(
+        (via_footprint, footprint_stats.expanded),
+        (jumped, stats.expanded)
+    );
+
+    // A spent budget stops at the jump point closest to the goal
+    p.max_expansions = Some(2);
+    let partial = jps_traced(start, goal, &p, &mut stats);
+    assert!(!partial.is_complete());
+    assert_eq!(stats.expanded, 2);
+    assert_eq!(partial.path()[0], start);
+    assert!(partial.path().len() > 1);
+    assert_eq!(jps(start, goal, &p), None);
+}
+
+fn random_walls(rng: &mut StdRng, bounds: GridBounds, density: f64) -> HashSet<GridCoord> {
+    (0..bounds.width)
+        .flat_map(|x| (0..bounds.height).map(move |y| GridCoord::new(x, y)))
+        .filter(|_| rng.gen_bool(density))
+        .collect()
+}
+
+#[test]
+fn hpa_finds_near_optimal_paths() {
+    let mut rng = StdRng::seed_from_u64(11);
+    for movement in [MovementMode::FourWay, MovementMode::EightWayNoCornerCut] {
+        let bounds = GridBounds::new(48, 40);
+        let walls = random_walls(&mut rng, bounds, 0.2);
+        let wall_list: Vec<GridCoord> = walls.iter().copied().collect();
+        let p = policy(&wall_list, bounds, movement);
+        let graph = HpaGraph::build(bounds, movement, 8, walls.clone());
+
+        for _ in 0..30 {
+            let start = GridCoord::new(rng.gen_range(0..48), rng.gen_range(0..40));
+            let goal = GridCoord::new(rng.gen_range(0..48), rng.gen_range(0..40));
+            if walls.contains(&start) || walls.contains(&goal) {
+                continue;
+            }
+            let optimal = astar(start, goal, &p).map(|path| path_cost(&path));
+            let found = graph.find_path(start, goal, &p);
+            assert_eq!(optimal.is_some(), found.is_some(), "{start:?} -> {goal:?}");
+            let mut stats = SearchStats::default();
+            assert_eq!(graph.find_path_traced(start, goal, &p, &mut stats), found);
+            assert_eq!(stats.path_len, found.as_ref().map_or(0, Vec::len));
+            let (Some(optimal), Some(path)) = (optimal, found) else {
+                continue;
+            };
+
+            assert_eq!((path[0], *path.last().unwrap()), (start, goal));
+            assert!(
+                path.windows(2)
+                    .all(|w| astar(w[0], w[1], &p).map(|s| s.len()) == Some(2))
+            );
+            // HPA* detours through entrances, by up to about a cluster on short routes
+            let slack = 2 * 8 * ORTHOGONAL_COST;
+            assert!(
+                path_cost(&path) * 10 <= optimal * 12 + slack * 10,
+                "{start:?} -> {goal:?}"
+            );
+        }
+    }
+}
+
+#[test]
+fn hpa_incremental_update_matches_rebuild() {
+    let mut rng = StdRng::seed_from_u64(5);
+    let bounds = GridBounds::new(40, 40).wrapping(true);
+    let walls = random_walls(&mut rng, bounds, 0.15);
+    let mut graph = HpaGraph::build(bounds, MovementMode::EightWay, 8, walls.clone());
+
+    // Wall off a column, then knock a hole in it
+    let mut changed = walls.clone();
+    changed.extend((0..40).filter(|&y| y != 17).map(|y| GridCoord::new(20, y)));
+    changed.remove(&GridCoord::new(3, 3));
+    graph.update_walls(changed.clone());
+    let rebuilt = HpaGraph::build(bounds, MovementMode::EightWay, 8, changed.clone());
+
+    let wall_list: Vec<GridCoord> = changed.iter().copied().collect();
+    let p = policy(&wall_list, bounds, MovementMode::EightWay);
+    for _ in 0..30 {
+        let start = GridCoord::new(rng.gen_range(0..40), rng.gen_range(0..40));
+        let goal = GridCoord::new(rng.gen_range(0..40), rng.gen_range(0..40));
+        if changed.contains(&start) || changed.contains(&goal) {
+            continue;
+        }
+        assert_eq!(
+            graph.find_path(start, goal, &p),
+            rebuilt.find_path(start, goal, &p)
+        );
+    }
+}
+
+#[test]
+fn flow_field_leads_every_cell_to_a_target() {
+    let walls: Vec<GridCoord> = (1..9).map(|x| GridCoord::new(x, 4)).collect();
+    let p = policy(
+        &walls,
+        GridBounds::new(10, 8),
+        MovementMode::EightWayNoCornerCut,
+    );
+    let targets = [GridCoord::new(2, 7), GridCoord::new(8, 0)];
+    let field = FlowField::build(targets, &p, i32::MAX);
+
+    for x in 0..10 {
+        for y in 0..8 {
+            let cell = GridCoord::new(x, y);
+            if walls.contains(&cell) {
+                continue;
+            }
+            let path = field.path_from(cell, &p);
+            assert!(targets.contains(path.last().unwrap()), "{cell:?}");
+            assert_eq!(
+                path_cost(&path) as i32,
+                field.distance(cell).unwrap(),
+                "{cell:?}"
+            );
+        }
+    }
+    assert_eq!(field.dir_at(targets[0]), None);
+}
+
+#[test]
+fn astar_breaks_ties_by_heuristic_then_coordinate() {
+    // Open field: many equal-cost paths, the chosen one must not depend on heap internals
+    let p = policy(&[], GridBounds::new(6, 6), MovementMode::FourWay);
+    let start = GridCoord::new(0, 0);
+    let goal = GridCoord::new(3, 3);
+    let path = astar(start, goal, &p).unwrap();
+    for _ in 0..10 {
+        assert_eq!(astar(start, goal, &p).as_ref(), Some(&path));
+    }
+    assert_eq!(path_cost(&path), 6 * ORTHOGONAL_COST);
+    // lower coordinates win ties: the path runs along x before climbing in y
+    assert_eq!(path[1], GridCoord::new(1, 0));
+}
+
+#[test]
+fn astar_partial_stops_at_budget_and_unreachable_goals() {
+    // Goal sealed in a box: unbounded A* would flood the map, partial search returns the closest cell
+    let goal = GridCoord::new(10, 5);
+    let walls: Vec<GridCoord> = [
+        (9, 4),
+        (10, 4),
+        (11, 4),
+        (9, 5),
+        (11, 5),
+        (9, 6),
+        (10, 6),
+        (11, 6),
+    ]
+    .into_iter()
+    .map(|(x, y)| GridCoord::new(x, y))
+    .collect();
+    let mut p = policy(&walls, GridBounds::new(16, 12), MovementMode::FourWay);
+    let start = GridCoord::new(0, 5);
+
+    let result = astar_partial(start, goal, &p);
+    assert!(!result.is_complete());
+    assert_eq!(result.path().last(), Some(&GridCoord::new(8, 5)));
+    assert_eq!(astar(start, goal, &p), None);
+
+    // A tight budget stops early but still heads towards the goal
+    p.max_expansions = Some(4);
+    let PathResult::Partial(path) = astar_partial(start, GridCoord::new(15, 5), &p) else {
+        panic!("budget of 4 cannot reach the far side");
+    };
+    assert_eq!(path[0], start);
+    assert!(path.len() > 1 && path.iter().all(|c| c.y == 5));
+}
+
+#[test]
+fn astar_traced_reports_search_stats() {
+    let walls: Vec<GridCoord> = (0..4).map(|y| GridCoord::new(3, y)).collect();
+    let mut p = policy(&walls, GridBounds::new(8, 6), MovementMode::FourWay);
+    let (start, goal) = (GridCoord::new(0, 0), GridCoord::new(6, 0));
+
+    // Tracing does not change the result
+    let mut stats = SearchStats::default();
+    let result = astar_footprint_traced(start, goal, Footprint::SINGLE, &p, &mut stats);
+    assert_eq!(result, astar_partial(start, goal, &p));
+    assert_eq!(stats.path_len, result.path().len());
+    assert_eq!(stats.expanded, stats.explored.len());
+    assert_eq!(stats.explored[0], start);
+    assert!(stats.open_peak > 0);
+    let distinct: HashSet<GridCoord> = stats.explored.iter().copied().collect();
+    assert_eq!(distinct.len(), stats.explored.len());
+    assert!(!stats.explored.iter().any(|c| walls.contains(c)));
+
+    // A budgeted search reports exactly the budget, and stale stats are overwritten
+    p.max_expansions = Some(5);
+    let result = astar_footprint_traced(start, goal, Footprint::SINGLE, &p, &mut stats);
+    assert!(!result.is_complete());
+    assert_eq!(stats.expanded, 5);
+    assert_eq!(stats.path_len, result.path().len());
+}
+
+// `DefaultRules`, plus a one-way cell at (3, 3) that cannot be entered from the west
+struct OneWayRules;
+
+impl Rules for OneWayRules {
+    fn can_enter(
+        &self,
+        occ: &OccupancyIndex,
+        mover: Entity,
+        from: GridCoord,
+        to: GridCoord,
+    ) -> MoveCheck {
+        if to == GridCoord::new(3, 3) && from == GridCoord::new(2, 3) {
+            return MoveCheck::Blocked;
+        }
+        DefaultRules.can_enter(occ, mover, from, to)
+    }
+}
+
+#[test]
+fn rules_policy_agrees_with_check_step() {
+    let mut world = World::new();
+    let mover = world.spawn_empty().id();
+    let wall = world.spawn_empty().id();
+    let mut occ = OccupancyIndex::default();
+    occ.set_bounds(Some(GridBounds::new(8, 8)));
+    occ.insert(Layer::Blockers, GridCoord::new(1, 3), mover);
+    for c in [(4, 2), (4, 4), (5, 5), (2, 6)] {
+        occ.insert(Layer::Blockers, GridCoord::new(c.0, c.1), wall);
+    }
+
+    for movement in [
+        MovementMode::FourWay,
+        MovementMode::EightWay,
+        MovementMode::EightWayNoCornerCut,
+    ] {
+        let policy = RulesPolicy::new(&OneWayRules, &occ, movement, mover);
+        for y in 0..8 {
+            for x in 0..8 {
+                let from = GridCoord::new(x, y);
+                let neighbours = policy.neighbours(from);
+                for &dir in &Dir::ALL {
+                    let check = check_step(
+                        &OneWayRules,
+                        &occ,
+                        movement,
+                        mover,
+                        Footprint::SINGLE,
+                        from,
+                        dir,
+                    );
+                    assert_eq!(
+                        neighbours.contains(&occ.step(from, dir)),
+                        matches!(check, MoveCheck::Allow),
+                        "{movement:?} {from:?} {dir:?}"
+                    );
+                }
+            }
+        }
+
+        // Every step of a found path is one the rules accept, one-way cell included
+        let path = astar(GridCoord::new(1, 3), GridCoord::new(6, 3), &policy).unwrap();
+        for w in path.windows(2) {
+            let (dx, dy) = occ.delta(w[0], w[1]);
+            let dir = Dir::from_offset(dx, dy).unwrap();
+            let check = check_step(
+                &OneWayRules,
+                &occ,
+                movement,
+                mover,
+                Footprint::SINGLE,
+                w[0],
+                dir,
+            );
+            assert!(matches!(check, MoveCheck::Allow));
+        }
+    }
+
+    // The mover may stand on its own cell; a shared policy treats it as taken
+    let own = RulesPolicy::new(&OneWayRules, &occ, MovementMode::FourWay, mover);
+    let shared = RulesPolicy::new(
+        &OneWayRules,
+        &occ,
+        MovementMode::FourWay,
+        Entity::PLACEHOLDER,
+    );
+    assert!(own.passable(GridCoord::new(1, 3)));
+    assert!(!shared.passable(GridCoord::new(1, 3)));
+}
+
+#[test]
+fn dijkstra_map_goes_round_one_way_cells() {
+    // The one-way cell at (3, 3) sits between (2, 3) and the goal, and only lets walkers in
+    // from the other three sides
+    let occ = {
+        let mut occ = OccupancyIndex::default();
+        occ.set_bounds(Some(GridBounds::new(8, 8)));
+        occ
+    };
+    let policy = RulesPolicy::new(
+        &OneWayRules,
+        &occ,
+        MovementMode::FourWay,
+        Entity::PLACEHOLDER,
+    );
+    let goal = GridCoord::new(5, 3);
+    let field = DijkstraMap::build([goal], &policy, i32::MAX);
+
+    for y in 0..8 {
+        for x in 0..8 {
+            let cell = GridCoord::new(x, y);
+            let best = astar(cell, goal, &policy).map(|path| path_cost(&path) as i32);
+            assert_eq!(field.get(cell), best, "{cell:?}");
+        }
+    }
+    // three steps straight along the row, were it not for the one-way cell
+    let beside = GridCoord::new(2, 3);
+    assert_eq!(field.get(beside), Some(5 * ORTHOGONAL_COST as i32));
+    assert_eq!(field.path_from(beside, &policy).len(), 6);
+}
+
+#[test]
+fn keyed_path_fetches_keys_before_locked_doors() {
+    //   .......
+    //   ###D###   door at (3, 1) needs key 5, lying at (6, 0)
+    //   S.....k   start (0, 0), goal (3, 2)
+    let bounds = GridBounds::new(7, 3);
+    let walls: Vec<GridCoord> = [0, 1, 2, 4, 5, 6]
+        .into_iter()
+        .map(|x| GridCoord::new(x, 1))
+        .collect();
+    let p = policy(&walls, bounds, MovementMode::FourWay);
+    let (start, goal, key) = (
+        GridCoord::new(0, 0),
+        GridCoord::new(3, 2),
+        GridCoord::new(6, 0),
+    );
+    let door = GridCoord::new(3, 1);
+    let mut layout = KeyLayout::default();
+    layout.doors.insert(door, Some(5));
+
+    assert_eq!(
+        keyed_path(start, goal, KeySet::default(), &layout, &p),
+        None
+    );
+
+    // Holding the key already: straight through the door
+    let held = KeySet::default().with(5);
+    let direct = keyed_path(start, goal, held, &layout, &p).unwrap();
+    assert_eq!(direct.path.len(), 6);
+
+    // Otherwise the route runs past the door to the key and back
+    layout.keys.insert(key, 5);
+    let fetch = keyed_path(start, goal, KeySet::default(), &layout, &p).unwrap();
+    let at = |c| fetch.path.iter().position(|&x| x == c).unwrap();
+    assert!(at(key) < at(door));
+    assert!(fetch.keys.contains(5));
+    assert_eq!(path_cost(&fetch.path), 11 * ORTHOGONAL_COST);
+    let mut stats = SearchStats::default();
+    let traced = keyed_path_traced(start, goal, KeySet::default(), &layout, &p, &mut stats);
+    assert_eq!(traced.as_ref(), Some(&fetch));
+    assert_eq!(stats.path_len, fetch.path.len());
+    assert!(stats.expanded >= fetch.path.len() - 1);
+
+    // A plain door opens without any key
+    layout.doors.insert(door, None);
+    layout.keys.clear();
+    assert!(keyed_path(start, goal, KeySet::default(), &layout, &p).is_some());
+}
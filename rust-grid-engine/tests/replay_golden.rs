@@ -1,15 +1,22 @@
+use bevy::ecs::system::RunSystemOnce;
 use bevy::prelude::*;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 use serde::{Deserialize, Serialize};
 use std::fs;
 
-use rust_grid_engine::engine::replay::{ActiveReplay, Replay, ReplayConfig, ReplayTickTimer};
-use rust_grid_engine::engine::{EnginePlugin, TurnNumber};
-use rust_grid_engine::scenes::{GameScene, PauseState, ScenePlugin};
+use rust_grid_engine::ai::Difficulty;
+use rust_grid_engine::engine::replay::{
+    ActiveReplay, RecordedInput, Replay, ReplayConfig, ReplayLog, ReplayTickTimer,
+};
+use rust_grid_engine::engine::{EnginePlugin, TurnNumber, TurnRng};
+use rust_grid_engine::scenes::{GameScene, PauseState, ScenePlugin, spawn_level};
 
 // Adjust these paths to your actual components/types:
 use rust_grid_engine::components::{AI, Actor, Blocking, Door, Goal, Player, Position, Trap};
 use rust_grid_engine::engine::rules::GetCaught;
-use rust_grid_engine::grid::GridCoord;
+use rust_grid_engine::grid::{Dir, GridCoord, OccupancyIndex};
+use rust_grid_engine::intents::InputEvent;
 use rust_grid_engine::map::load_level_from_json;
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
@@ -97,17 +104,38 @@ fn setup_test_world(world: &mut World) {
     // enemies
     for e in level.enemies {
         let coord = GridCoord::new(e.x, e.y);
-        world.spawn((
+        let mut enemy = world.spawn((
             Actor,
             AI,
             Blocking,
             Position(coord),
             PendingIntent(Intent::Wait),
         ));
+        // slow and fast enemies keep their pace in the snapshots
+        if let Some(speed) = e.speed {
+            enemy.insert(speed);
+        }
     }
 }
 
 fn run_replay_and_capture(replay: &Replay) -> Vec<Snapshot> {
+    run_replay_on(setup_test_world, replay)
+}
+
+// graveyard.json spawned the way the game spawns it, for its slow and fast enemies
+fn setup_graveyard(world: &mut World) {
+    let bytes = fs::read("assets/levels/graveyard.json").unwrap();
+    let level = load_level_from_json(&bytes).expect("invalid level JSON");
+    world
+        .run_system_once(
+            move |mut commands: Commands, mut occ: ResMut<OccupancyIndex>| {
+                spawn_level(&mut commands, &mut occ, level.clone(), None);
+            },
+        )
+        .unwrap();
+}
+
+fn run_replay_on(setup: impl FnOnce(&mut World), replay: &Replay) -> Vec<Snapshot> {
     let mut app = App::new();
     app.add_plugins(MinimalPlugins);
     app.add_plugins(bevy::state::app::StatesPlugin);
@@ -117,7 +145,7 @@ fn run_replay_and_capture(replay: &Replay) -> Vec<Snapshot> {
 
     {
         let world = app.world_mut();
-        setup_test_world(world);
+        setup(world);
     }
 
     // Deterministic “instant replay tick” for tests
@@ -145,6 +173,9 @@ fn run_replay_and_capture(replay: &Replay) -> Vec<Snapshot> {
         let mut active = app.world_mut().resource_mut::<ActiveReplay>();
         active.start(replay.clone());
     }
+    // the AIs play on the recorded seed and difficulty, as `start_replay_mode` sets them
+    app.insert_resource(TurnRng(StdRng::seed_from_u64(replay.seed)));
+    app.insert_resource(replay.difficulty);
 
     // Reset turn to first recorded turn (prevents “stuck at 0” if replay begins at 1+)
     {
@@ -180,6 +211,49 @@ fn run_replay_and_capture(replay: &Replay) -> Vec<Snapshot> {
     app.world_mut().remove_resource::<SnapshotLog>().unwrap().0
 }
 
+#[test]
+fn replays_keep_the_difficulty_they_were_played_on() {
+    let mut log = ReplayLog::default();
+    log.record(0, InputEvent::Move(Dir::Up));
+    let saved = Replay::from_log(7, Difficulty::Hard, &log);
+    let bytes = serde_json::to_vec(&saved).unwrap();
+    let loaded: Replay = serde_json::from_slice(&bytes).unwrap();
+    assert_eq!(loaded.difficulty, Difficulty::Hard);
+
+    // replays saved before difficulty was recorded play on the default
+    let old: Replay = serde_json::from_slice(br#"{ "seed": 7, "inputs": [] }"#).unwrap();
+    assert_eq!(old.difficulty, Difficulty::Normal);
+}
+
+#[test]
+fn fast_and_slow_enemies_replay_at_the_same_pace() {
+    let inputs = (0..16)
+        .map(|turn| RecordedInput {
+            turn,
+            input: InputEvent::Move(if turn % 2 == 0 { Dir::Up } else { Dir::Down }),
+        })
+        .collect();
+    let replay = Replay {
+        seed: 11,
+        difficulty: Difficulty::Normal,
+        inputs,
+    };
+    let first = run_replay_on(setup_graveyard, &replay);
+    let second = run_replay_on(setup_graveyard, &replay);
+    assert_eq!(first, second);
+
+    // On the first turn only the ghost, at speed 200, has the energy to act, and it moves twice
+    let ghost = GridCoord::new(13, 7);
+    let (before, after) = (&first[0].ais, &first[1].ais);
+    assert!(before.contains(&ghost));
+    let moved: Vec<_> = after.iter().filter(|c| !before.contains(c)).collect();
+    assert_eq!(moved.len(), 1, "{before:?} -> {after:?}");
+    assert_eq!(
+        (moved[0].x - ghost.x).abs() + (moved[0].y - ghost.y).abs(),
+        2
+    );
+}
+
 #[test]
 fn replay_is_deterministic_twice() {
     // Load replay (seed + inputs)